@@ -11,7 +11,7 @@ use wasm_bindgen::{
     JsCast
 };
 use protocol::{
-    message::Message,
+    message::{Message, PROTOCOL_VERSION},
     entity::*,
     block::*,
     map::Map,
@@ -20,16 +20,17 @@ use protocol::{
 use web_sys::{
     WebSocket,
     Event,
-    MessageEvent
+    MessageEvent,
+    KeyboardEvent,
+    HtmlInputElement,
 };
 use std::{
     rc::Rc,
+    cell::Cell,
     panic,
     collections::HashMap,
 };
 
-const CENTER_POINT: u64 = 9_223_372_036_854_775_808;
-
 #[wasm_bindgen]
 extern "C" {
     // Use `js_namespace` here to bind `console.log(..)` instead of just
@@ -61,9 +62,12 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
     let keyboard = KeyboardManager::new();
     let mut entities: HashMap<u64, Entity> = HashMap::new();
     let mut player_id: u64 = 0;
+    let mut server_tick: u64 = 0;
     let mut map: Map = Map::new();
     let websocket2 = Rc::clone(&websocket);
     let mut waiting_ping: Option<f64> = None;
+    let disconnected = Rc::new(Cell::new(false));
+    let disconnected2 = Rc::clone(&disconnected);
     
     for image in &mut images {
         image.set_origin((0.0, image.get_size().1 as f64));
@@ -72,13 +76,23 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
 
     
 
-    websocket.send_with_str(&Message::InitServer{username: String::from("Mubelotix"), screen_width: canvas.get_size().0, screen_height: canvas.get_size().1, password: None}.encode()).expect("can't send init message");
+    setup_chat_input(Rc::clone(&websocket));
+
+    websocket.send_with_str(&Message::InitServer{username: String::from("Mubelotix"), screen_width: canvas.get_size().0, screen_height: canvas.get_size().1, password: None, protocol_version: PROTOCOL_VERSION}.encode()).expect("can't send init message");
     let message = Closure::wrap(Box::new(move |event: MessageEvent| {
-        
+        if disconnected.get() {
+            return;
+        }
+
         if let Some(data) = event.data().as_string() {
             match Message::decode(data).expect("can't deserialize message") {
-                Message::ChatMessage{sender_id: _, receiver_id: _, message} => {
-                    println!("{}", message);
+                Message::ChatMessage{sender_id, sender_name, receiver_id, receiver_name, message} => {
+                    match (sender_id, receiver_id) {
+                        (0, _) => show_chat_message("system", &message),
+                        (_, 0) => show_chat_message("", &format!("<{}> {}", sender_name, message)),
+                        (sender_id, _) if sender_id == player_id => show_chat_message("whisper", &format!("to {}: {}", receiver_name, message)),
+                        _ => show_chat_message("whisper", &format!("from {}: {}", sender_name, message)),
+                    }
                 },
                 Message::Chunk(chunk) => {
                     map.set_chunk(chunk.x, chunk.y, chunk.blocks);
@@ -86,9 +100,17 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
                 Message::CreateEntity(entity) => {
                     entities.insert(entity.get_id(), entity);
                 },
-                Message::Tick => {
+                Message::RemoveEntity{id} => {
+                    entities.remove(&id);
+                },
+                Message::Tick(tick) => {
+                    if server_tick != 0 && tick > server_tick + 1 {
+                        println!("the server skipped {} ticks", tick - server_tick - 1);
+                    }
+                    server_tick = tick;
                     if player_id != 0 {
                         let player = &mut entities.get_mut(&player_id).unwrap();
+                        let typing = is_typing();
                         
                         let mut direction_x: i8 = 0;
                         let mut direction_y: i8 = 0;
@@ -104,11 +126,15 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
                         if keyboard.get_key(Key::S) {
                             direction_y += 1;
                         }
-                        if keyboard.get_key(Key::P) {
+                        if keyboard.get_key(Key::P) && waiting_ping.is_none() {
                             waiting_ping = Some(window.performance().unwrap().now());
-                            websocket.send_with_str(&Message::Ping.encode()).unwrap();
+                            websocket.send_with_str(&Message::Ping(server_tick).encode()).unwrap();
                         }
 
+                        if typing {
+                            direction_x = 0;
+                            direction_y = 0;
+                        }
                         match (direction_x, direction_y) {
                             (0,0) => (),
                             (0,y) => {
@@ -148,7 +174,7 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
                         //println!("{:?}", player.get_readable_coords());
                         
                         let x = -25 * 40 + (canvas.get_size().0 / 2) as isize - player.coords.x.get_additionnal() as isize;
-                        let y = -15 as isize * 40 + (canvas.get_size().1 / 2) as isize - player.coords.y.get_additionnal() as isize;
+                        let y = -15_isize * 40 + (canvas.get_size().1 / 2) as isize - player.coords.y.get_additionnal() as isize;
                             
                             for i in 0..50 {
                                 for j in 0..30 {
@@ -157,10 +183,10 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
 
                                     match map[(x1 + i as u64, y1 + j as u64)].get_block_code() {
                                         BlockCode::SimpleSlab => {
-                                            canvas.draw_image((x + (i as isize + 25) * 40) as f64, (y + (j as isize + 15) * 40) as f64 + 80.0, &images[0]);
+                                            canvas.draw_image((x + (i + 25) * 40) as f64, (y + (j + 15) * 40) as f64 + 80.0, &images[0]);
                                         },
                                         BlockCode::SimpleWall => {
-                                            canvas.draw_image((x + (i as isize + 25) * 40) as f64, (y + (j as isize + 15) * 40) as f64 + 80.0, &images[1])
+                                            canvas.draw_image((x + (i + 25) * 40) as f64, (y + (j + 15) * 40) as f64 + 80.0, &images[1])
                                         }
                                     }
                                 }
@@ -172,37 +198,191 @@ fn main(mut images: Vec<Image>, websocket: Rc<WebSocket>) {
                 Message::UnloadChunk{x, y} => {
                     map.delete_chunk(x, y);
                 },
-                Message::InitServer{username: _, screen_width: _, screen_height: _, password: _} => {
+                Message::InitServer{..} => {
                     panic!("server is not intented to connect");
                 },
                 Message::InitClient{id} => {
                     player_id = id;
                 },
-                Message::Ping => {
-                    if waiting_ping == None {
-                        websocket.send_with_str(&Message::Ping.encode()).unwrap();
-                    } else {
-                        let waiting_ping = waiting_ping.unwrap();
-                        println!("ping: {}ms", window.performance().unwrap().now() - waiting_ping);
+                Message::ServerInfo{name, motd, ..} => {
+                    if let Some(document) = window.document() {
+                        document.set_title(&name);
+                    }
+                    if !motd.is_empty() {
+                        show_chat_message("system", &motd);
+                    }
+                },
+                Message::Ping(id) => {
+                    websocket.send_with_str(&Message::Pong(id).encode()).unwrap();
+                },
+                Message::Pong(_) => {
+                    if let Some(sent) = waiting_ping.take() {
+                        println!("ping: {}ms", window.performance().unwrap().now() - sent);
                     }
                 },
                 Message::TpEntity{id, coords} => {
                     entities.entry(id).or_default().coords = coords;
                 }
-                Message::Kick(reason) => {
-                    panic!("You have been kicked because: {}", reason);
+                Message::Kick{reason, message} => {
+                    disconnected.set(true);
+                    show_disconnect_screen(&format!("You have been kicked ({})", reason), &message);
+                    let _ = websocket.close();
                 }
             };
         } else {
             println!("can't read message as string");
         }
     }) as Box<dyn FnMut(MessageEvent)>);
+    let close = Closure::wrap(Box::new(move |_event: Event| {
+        if !disconnected2.get() {
+            disconnected2.set(true);
+            show_disconnect_screen("Connection lost", "The server closed the connection.");
+        }
+    }) as Box<dyn FnMut(Event)>);
     websocket2
         .add_event_listener_with_callback("message", message.as_ref().unchecked_ref())
         .unwrap();
+    websocket2
+        .add_event_listener_with_callback("close", close.as_ref().unchecked_ref())
+        .unwrap();
     message.forget();
+    close.forget();
+}
+
+/// Display a window explaining why the game stopped.
+fn show_disconnect_screen(title: &str, details: &str) {
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => {
+            println!("no document");
+            return;
+        }
+    };
+    let body = match document.body() {
+        Some(body) => body,
+        None => {
+            println!("no body");
+            return;
+        }
+    };
+    let element = match document.create_element("div") {
+        Ok(element) => element,
+        Err(error) => {
+            println!("{:?}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = element.set_attribute("class", "disconnect_window") {
+        println!("can't set class to disconnect_window because {:?}", error);
+    }
+    element.set_inner_html(&format!("{}<br>{}", escape_html(title), escape_html(details)));
+    if let Err(error) = body.append_child(&element) {
+        println!("can't append child because {:?}", error);
+    }
+}
+
+/// Whether the player is writing in the chat, so that the keys must not move the character.
+fn is_typing() -> bool {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.active_element())
+        .map(|element| element.id() == "chat_input")
+        .unwrap_or(false)
+}
+
+/// Send the content of the chat input when Enter is pressed.
+/// Enter also focuses the chat input, and Escape leaves it.
+fn setup_chat_input(websocket: Rc<WebSocket>) {
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => {
+            println!("no document");
+            return;
+        }
+    };
+    let input = match document.get_element_by_id("chat_input").and_then(|element| element.dyn_into::<HtmlInputElement>().ok()) {
+        Some(input) => input,
+        None => {
+            println!("no chat input");
+            return;
+        }
+    };
+
+    let input2 = input.clone();
+    let keydown = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+        match event.key().as_str() {
+            "Enter" if is_typing() => {
+                let message = input2.value();
+                if !message.trim().is_empty() {
+                    // the server fills in the sender
+                    let message = Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id: 0, receiver_name: String::new(), message};
+                    if websocket.send_with_str(&message.encode()).is_err() {
+                        println!("can't send the chat message");
+                    }
+                }
+                input2.set_value("");
+                let _ = input2.blur();
+            },
+            "Enter" => {
+                let _ = input2.focus();
+            },
+            "Escape" => {
+                let _ = input2.blur();
+            },
+            _ => (),
+        }
+    }) as Box<dyn FnMut(KeyboardEvent)>);
+    document
+        .add_event_listener_with_callback("keydown", keydown.as_ref().unchecked_ref())
+        .unwrap();
+    keydown.forget();
+}
+
+/// Add a line to the chat area. `class` can be used to style system messages and whispers.
+fn show_chat_message(class: &str, text: &str) {
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => {
+            println!("no document");
+            return;
+        }
+    };
+    let messages = match document.get_element_by_id("chat_messages") {
+        Some(messages) => messages,
+        None => {
+            println!("{}", text);
+            return;
+        }
+    };
+    let element = match document.create_element("div") {
+        Ok(element) => element,
+        Err(error) => {
+            println!("{:?}", error);
+            return;
+        }
+    };
 
+    if !class.is_empty() {
+        if let Err(error) = element.set_attribute("class", class) {
+            println!("can't set class to {} because {:?}", class, error);
+        }
+    }
+    element.set_inner_html(&escape_html(text));
+    if let Err(error) = messages.append_child(&element) {
+        println!("can't append child because {:?}", error);
+    }
+    // only the last messages are kept
+    while messages.child_element_count() > 100 {
+        if let Some(first) = messages.first_element_child() {
+            first.remove();
+        }
+    }
+    messages.set_scroll_top(messages.scroll_height());
+}
 
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
 }
 
 fn setup_websocket(images: Vec<Image>) {
@@ -212,11 +392,11 @@ fn setup_websocket(images: Vec<Image>) {
     // TODO clear this shit
     let websocket2 = Rc::clone(&websocket);
     let open = Closure::wrap(Box::new(move |_event: Event| {
-        let images: Vec<Image> = (&images).clone();
+        let images: Vec<Image> = images.clone();
         let websocket = Rc::clone(&websocket2);
         main(images, websocket);
     }) as Box<dyn FnMut(Event)>);
-    let error = Closure::wrap(Box::new(move |event: Event| {
+    let error = Closure::wrap(Box::new(move |_event: Event| {
         panic!("Can't connect to server.");
     }) as Box<dyn FnMut(Event)>);
     websocket
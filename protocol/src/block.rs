@@ -1,4 +1,5 @@
 use serde::{Serialize, Deserialize};
+use std::str::FromStr;
 
 #[repr(u16)]
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
@@ -95,6 +96,44 @@ impl From<u16> for BlockCode {
     }
 }
 
+impl FromStr for BlockCode {
+    type Err = &'static str;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "simple_slab" => Ok(BlockCode::SimpleSlab),
+            "simple_wall" => Ok(BlockCode::SimpleWall),
+            _ => Err("unknow block"),
+        }
+    }
+}
+
+impl FromStr for Orientation {
+    type Err = &'static str;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "up" => Ok(Orientation::Up),
+            "down" => Ok(Orientation::Down),
+            "left" => Ok(Orientation::Left),
+            "right" => Ok(Orientation::Right),
+            _ => Err("unknow orientation"),
+        }
+    }
+}
+
+impl Orientation {
+    /// The orientation after a quarter turn clockwise, as seen on the screen.
+    pub fn rotate_clockwise(self) -> Self {
+        match self {
+            Orientation::Up => Orientation::Right,
+            Orientation::Right => Orientation::Down,
+            Orientation::Down => Orientation::Left,
+            Orientation::Left => Orientation::Up,
+        }
+    }
+}
+
 impl From<u8> for Orientation {
     fn from(code: u8) -> Self {
         match code {
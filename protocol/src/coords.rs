@@ -30,9 +30,9 @@ impl Coords {
     }
 }
 
-impl Into<(u64, u64)> for Coords {
-    fn into(self) -> (u64, u64) {
-        (self.x.main, self.y.main)
+impl From<Coords> for (u64, u64) {
+    fn from(coords: Coords) -> Self {
+        (coords.x.main, coords.y.main)
     }
 }
 
@@ -111,9 +111,9 @@ impl SingleAxis {
     }
 }
 
-impl Into<f64> for SingleAxis {
-    fn into(self) -> f64 {
-        self.main as f64 * 40.0 + self.additionnal as f64
+impl From<SingleAxis> for f64 {
+    fn from(axis: SingleAxis) -> Self {
+        axis.main as f64 * 40.0 + axis.additionnal as f64
     }
 }
 
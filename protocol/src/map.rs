@@ -15,6 +15,16 @@ impl Map {
         }
     }
 
+    /// Every chunk stored in the map, in no particular order.
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Remove the chunks for which `f` returns false.
+    pub fn retain_chunks(&mut self, f: impl FnMut(&Chunk) -> bool) {
+        self.chunks.retain(f);
+    }
+
     pub fn print_info(&self) {
         println!("{} chunks loaded", self.chunks.len());
     }
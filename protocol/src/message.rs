@@ -1,20 +1,78 @@
-use crate::block::{Orientation, Chunk};
+use crate::block::Chunk;
 use crate::entity::Entity;
 use crate::coords::Coords;
 use serde::{Serialize, Deserialize};
+use std::fmt;
+
+/// Increased whenever the messages change in a way older clients or servers can't understand.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Message {
-    ChatMessage{sender_id: u64, receiver_id: u64, message: String},
+    /// A `sender_id` of 0 is the server, and a `receiver_id` of 0 is everyone.
+    /// The names are set by the server, since clients don't know every player; they are empty for the server and for everyone.
+    /// The server ignores the `sender_id` and the names sent by clients.
+    ChatMessage{sender_id: u64, #[serde(default)] sender_name: String, receiver_id: u64, #[serde(default)] receiver_name: String, message: String},
     Chunk(Chunk),
     UnloadChunk{x: u64, y: u64},
     CreateEntity(Entity),
-    InitServer{username: String, screen_width: u32, screen_height: u32, password: Option<String>},
+    RemoveEntity{id: u64},
+    /// Clients too old to send their `protocol_version` are read as using the version 0.
+    InitServer{username: String, screen_width: u32, screen_height: u32, password: Option<String>, #[serde(default)] protocol_version: u32},
     InitClient{id: u64},
-    Tick,
-    Ping,
+    /// Sent after `InitClient` to describe the server. The view size is in chunks.
+    ServerInfo{name: String, motd: String, protocol_version: u32, tick_rate: u32, player_count: u32, view_width: u64, view_height: u64},
+    /// Sent at the end of every server tick, with the number of the tick.
+    Tick(u64),
+    /// Must be answered by a `Pong` with the same id.
+    Ping(u64),
+    Pong(u64),
     TpEntity{id: u64, coords: Coords},
-    Kick(String)
+    /// The server is closing the connection.
+    Kick{reason: KickReason, message: String}
+}
+
+/// Why the server closed a connection.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum KickReason {
+    Cheating,
+    Timeout,
+    Banned,
+    ServerStopping,
+    VersionMismatch,
+    /// Kicked by an operator.
+    Kicked,
+    /// The password does not match the account, or a password is required.
+    WrongPassword,
+    /// The account does not exist and can't be created.
+    UnknownAccount,
+    /// Someone is already playing with this account.
+    AlreadyConnected,
+    /// The username is not allowed.
+    InvalidUsername,
+    /// The whitelist is enabled and the player is not on it.
+    NotWhitelisted,
+    /// Too many messages, or too many invalid ones.
+    Flooding,
+}
+
+impl fmt::Display for KickReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KickReason::Cheating => write!(f, "cheating"),
+            KickReason::Timeout => write!(f, "timed out"),
+            KickReason::Banned => write!(f, "banned"),
+            KickReason::ServerStopping => write!(f, "server stopping"),
+            KickReason::VersionMismatch => write!(f, "version mismatch"),
+            KickReason::Kicked => write!(f, "kicked by an operator"),
+            KickReason::WrongPassword => write!(f, "wrong password"),
+            KickReason::UnknownAccount => write!(f, "unknown account"),
+            KickReason::AlreadyConnected => write!(f, "already connected"),
+            KickReason::InvalidUsername => write!(f, "invalid username"),
+            KickReason::NotWhitelisted => write!(f, "not whitelisted"),
+            KickReason::Flooding => write!(f, "flooding"),
+        }
+    }
 }
 
 impl Message {
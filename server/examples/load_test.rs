@@ -0,0 +1,162 @@
+//! Connects a few hundred simulated clients to a local server and reports
+//! latency and CPU usage.
+//!
+//! ```MD
+//! cargo run --release --example load_test -- [clients] [seconds]
+//! ```
+
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message as Frame;
+use protocol::message::{Message, PROTOCOL_VERSION};
+use server::game::Game;
+use server::config::Config;
+use server::tick::TickScheduler;
+use server::transport::WebSocketTransport;
+use server::transport::websocket::PROTOCOL_NAME;
+
+#[derive(Default)]
+struct ClientReport {
+	handshake: Option<Duration>,
+	tick_gaps: Vec<Duration>,
+}
+
+/// CPU time consumed by the calling thread (`thread` = true) or the whole process.
+#[cfg(target_os = "linux")]
+fn cpu_time(thread: bool) -> Duration {
+	let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+	let who = if thread { libc::RUSAGE_THREAD } else { libc::RUSAGE_SELF };
+	unsafe { libc::getrusage(who, &mut usage) };
+	let to_duration = |time: libc::timeval| Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000);
+	to_duration(usage.ru_utime) + to_duration(usage.ru_stime)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_time(_thread: bool) -> Duration {
+	Duration::default()
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+	if sorted.is_empty() {
+		return Duration::default();
+	}
+	sorted[(sorted.len() - 1) * percent / 100]
+}
+
+async fn simulate_client(address: String, username: String, duration: Duration) -> ClientReport {
+	let mut report = ClientReport::default();
+	let mut request = format!("ws://{}", address).into_client_request().unwrap();
+	request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(PROTOCOL_NAME));
+	let (mut websocket, _) = match tokio_tungstenite::connect_async(request).await {
+		Ok(websocket) => websocket,
+		Err(_) => return report,
+	};
+
+	let start = Instant::now();
+	let init = Message::InitServer{username, screen_width: 800, screen_height: 600, password: None, protocol_version: PROTOCOL_VERSION};
+	if websocket.send(Frame::Text(init.encode())).await.is_err() {
+		return report;
+	}
+
+	let mut last_tick: Option<Instant> = None;
+	let mut player = None;
+	let deadline = tokio::time::sleep(duration);
+	tokio::pin!(deadline);
+
+	loop {
+		let frame = tokio::select! {
+			_ = &mut deadline => break,
+			frame = websocket.next() => frame,
+		};
+		let data = match frame {
+			Some(Ok(Frame::Text(data))) => data,
+			Some(Ok(_)) => continue,
+			_ => break,
+		};
+		match Message::decode(data) {
+			Ok(Message::CreateEntity(entity)) if player.is_none() => player = Some(entity),
+			Ok(Message::InitClient{..}) => report.handshake = Some(start.elapsed()),
+			Ok(Message::Ping(id)) => {
+				let _ = websocket.send(Frame::Text(Message::Pong(id).encode())).await;
+			},
+			Ok(Message::Tick(_)) => {
+				let now = Instant::now();
+				if let Some(last_tick) = last_tick {
+					report.tick_gaps.push(now - last_tick);
+				}
+				last_tick = Some(now);
+
+				// Simulate inbound traffic: the player stays in place.
+				if let Some(player) = &player {
+					let message = Message::TpEntity{id: player.get_id(), coords: player.coords.clone()};
+					if websocket.send(Frame::Text(message.encode())).await.is_err() {
+						break;
+					}
+				}
+			},
+			_ => (),
+		}
+	}
+
+	let _ = websocket.close(None).await;
+	report
+}
+
+fn main() {
+	let mut args = env::args().skip(1);
+	let clients: usize = args.next().and_then(|arg| arg.parse().ok()).unwrap_or(300);
+	let seconds: u64 = args.next().and_then(|arg| arg.parse().ok()).unwrap_or(10);
+
+	let mut transport = WebSocketTransport::bind("127.0.0.1:0", Config::default().max_message_size, Config::default().max_queued_bytes).expect("cannot bind server");
+	let address = transport.local_addr().to_string();
+	let running = Arc::new(AtomicBool::new(true));
+	let server_running = Arc::clone(&running);
+
+	let server = thread::spawn(move || {
+		let config = Config::default();
+		let mut scheduler = TickScheduler::new(config.tick_rate);
+		let mut game = Game::with_config(config);
+		let start = Instant::now();
+		while server_running.load(Ordering::Relaxed) {
+			let tick = scheduler.wait();
+			game.accept_connections(&mut transport);
+			game.tick(tick);
+		}
+		(cpu_time(true), start.elapsed())
+	});
+
+	println!("connecting {} clients to {} for {}s", clients, address, seconds);
+	let runtime = tokio::runtime::Runtime::new().unwrap();
+	let process_cpu_start = cpu_time(false);
+	let reports = runtime.block_on(async {
+		let tasks: Vec<_> = (0..clients)
+			.map(|idx| tokio::spawn(simulate_client(address.clone(), format!("bot{}", idx), Duration::from_secs(seconds))))
+			.collect();
+		let mut reports = Vec::new();
+		for task in tasks {
+			reports.push(task.await.unwrap());
+		}
+		reports
+	});
+	let process_cpu = cpu_time(false) - process_cpu_start;
+
+	running.store(false, Ordering::Relaxed);
+	let (loop_cpu, loop_wall) = server.join().unwrap();
+
+	let mut handshakes: Vec<Duration> = reports.iter().filter_map(|report| report.handshake).collect();
+	let mut tick_gaps: Vec<Duration> = reports.iter().flat_map(|report| report.tick_gaps.iter().copied()).collect();
+	handshakes.sort();
+	tick_gaps.sort();
+
+	println!("{}/{} clients completed the handshake", handshakes.len(), clients);
+	println!("handshake latency: p50 {:?}, p99 {:?}, max {:?}", percentile(&handshakes, 50), percentile(&handshakes, 99), percentile(&handshakes, 100));
+	println!("interval between ticks: p50 {:?}, p99 {:?}, max {:?}", percentile(&tick_gaps, 50), percentile(&tick_gaps, 99), percentile(&tick_gaps, 100));
+	println!("game loop cpu: {:?} over {:?} ({:.1}%)", loop_cpu, loop_wall, loop_cpu.as_secs_f64() * 100.0 / loop_wall.as_secs_f64());
+	println!("process cpu (server and simulated clients): {:?} ({:.1}% of a core)", process_cpu, process_cpu.as_secs_f64() * 100.0 / seconds as f64);
+}
@@ -0,0 +1,354 @@
+//! Usernames and the passwords protecting them.
+//!
+//! Passwords are never stored: only their salted argon2 hash is.
+//! Since argon2 is slow on purpose, the passwords sent by the players are hashed by a [`PasswordWorker`].
+//! Accounts also hold the role of their player. Guests are always simple players.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use rand_core::OsRng;
+use serde::{Serialize, Deserialize};
+use protocol::message::KickReason;
+use crate::config::Config;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Account {
+	pub username: String,
+	/// In the PHC string format, including the salt.
+	pub password_hash: String,
+	#[serde(default)]
+	pub role: Role,
+}
+
+/// What a player is allowed to do. Each role can do everything the previous ones can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+	#[default]
+	Player,
+	/// Can manage the chat and the players.
+	Moderator,
+	/// Can edit the world and manage the server.
+	Operator,
+}
+
+impl fmt::Display for Role {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Role::Player => write!(f, "player"),
+			Role::Moderator => write!(f, "moderator"),
+			Role::Operator => write!(f, "operator"),
+		}
+	}
+}
+
+impl FromStr for Role {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"player" => Ok(Role::Player),
+			"moderator" => Ok(Role::Moderator),
+			"operator" => Ok(Role::Operator),
+			_ => Err("unknow role"),
+		}
+	}
+}
+
+/// How a player has been allowed to join.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Login {
+	/// The player gave the password of its account.
+	Authenticated,
+	/// The username has no account yet: one must be created with the hashed password with [`Accounts::set_password_hash`]
+	/// once the player is allowed to join.
+	Registered,
+	/// The username has no account and the player gave no password.
+	Guest,
+}
+
+/// The slow part of a login.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasswordJob {
+	/// Check the password sent for an existing account.
+	Verify{password: String, password_hash: String},
+	/// Hash the password of a new account.
+	Hash{password: String},
+}
+
+/// The result of a [`PasswordJob`]: the hash of the new account, if any, or why the player can't join.
+pub type PasswordResult = Result<Option<String>, (KickReason, String)>;
+
+impl PasswordJob {
+	pub fn run(self) -> PasswordResult {
+		match self {
+			PasswordJob::Verify{password, password_hash} => match PasswordHash::new(&password_hash) {
+				Ok(hash) if Argon2::default().verify_password(password.as_bytes(), &hash).is_ok() => Ok(None),
+				_ => Err((KickReason::WrongPassword, String::from("wrong password"))),
+			},
+			PasswordJob::Hash{password} => hash(&password).map(Some).map_err(|error| (KickReason::UnknownAccount, error)),
+		}
+	}
+}
+
+/// Runs the password jobs of the clients in a background thread, one at a time.
+pub struct PasswordWorker {
+	jobs: Sender<(u64, PasswordJob)>,
+	results: Receiver<(u64, PasswordResult)>,
+	/// The number of jobs whose result has not been received yet.
+	pending: usize,
+	/// The results received by [`PasswordWorker::wait`].
+	finished: Vec<(u64, PasswordResult)>,
+}
+
+impl Default for PasswordWorker {
+	fn default() -> Self {
+		let (jobs, job_receiver) = channel::<(u64, PasswordJob)>();
+		let (result_sender, results) = channel();
+		// stops when the worker is dropped
+		thread::spawn(move || {
+			for (client_id, job) in job_receiver {
+				if result_sender.send((client_id, job.run())).is_err() {
+					break;
+				}
+			}
+		});
+		PasswordWorker {
+			jobs,
+			results,
+			pending: 0,
+			finished: Vec::new(),
+		}
+	}
+}
+
+impl PasswordWorker {
+	pub fn start(&mut self, client_id: u64, job: PasswordJob) {
+		if self.jobs.send((client_id, job)).is_ok() {
+			self.pending += 1;
+		}
+	}
+
+	/// Return the results of the finished jobs, with the id of their client.
+	pub fn poll(&mut self) -> Vec<(u64, PasswordResult)> {
+		self.finished.extend(self.results.try_iter());
+		self.pending -= self.finished.len();
+		std::mem::take(&mut self.finished)
+	}
+
+	/// Wait for every job to finish. Their results are returned by the next [`PasswordWorker::poll`].
+	pub fn wait(&mut self) {
+		while self.finished.len() < self.pending {
+			match self.results.recv() {
+				Ok(result) => self.finished.push(result),
+				Err(_) => break,
+			}
+		}
+	}
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Accounts {
+	pub accounts: Vec<Account>,
+	/// Where the accounts are saved. Nothing is saved when `None`.
+	#[serde(skip)]
+	path: Option<PathBuf>,
+	/// Whether the accounts have changed since they were saved.
+	#[serde(skip)]
+	unsaved: bool,
+}
+
+/// Check that a username can be used: it must start with a letter, contain only ASCII letters, digits and underscores,
+/// respect the length limits of the config and not be reserved.
+pub fn validate_username(username: &str, config: &Config) -> Result<(), String> {
+	let length = username.chars().count();
+	if length < config.min_username_length || length > config.max_username_length {
+		return Err(format!("usernames must have between {} and {} characters", config.min_username_length, config.max_username_length));
+	}
+	if !username.starts_with(|c: char| c.is_ascii_alphabetic()) {
+		return Err(String::from("usernames must start with a letter"));
+	}
+	if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+		return Err(String::from("usernames can only contain letters, digits and underscores"));
+	}
+	if config.reserved_names.iter().any(|reserved| reserved.eq_ignore_ascii_case(username)) {
+		return Err(format!("the username {} is reserved", username));
+	}
+	Ok(())
+}
+
+fn hash(password: &str) -> Result<String, String> {
+	let salt = SaltString::generate(&mut OsRng);
+	Argon2::default()
+		.hash_password(password.as_bytes(), &salt)
+		.map(|hash| hash.to_string())
+		.map_err(|error| format!("can't hash the password: {}", error))
+}
+
+impl Accounts {
+	/// Load the accounts saved at `path`, or create an empty list saved there.
+	pub fn load(path: impl AsRef<Path>) -> Result<Accounts, String> {
+		let path = path.as_ref();
+		let mut accounts = match fs::read_to_string(path) {
+			Ok(data) => serde_yaml::from_str(&data).map_err(|error| format!("invalid account list {}: {}", path.display(), error))?,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => Accounts::default(),
+			Err(error) => return Err(format!("can't read {}: {}", path.display(), error)),
+		};
+		accounts.path = Some(path.to_path_buf());
+		Ok(accounts)
+	}
+
+	pub fn save(&self) -> Result<(), String> {
+		if let Some(path) = &self.path {
+			let temporary_path = path.with_extension("tmp");
+			let data = serde_yaml::to_string(self).map_err(|error| error.to_string())?;
+			fs::write(&temporary_path, data)
+				.and_then(|()| fs::rename(&temporary_path, path))
+				.map_err(|error| format!("can't save the accounts: {}", error))?;
+		}
+		Ok(())
+	}
+
+	/// Save the accounts if they have changed.
+	pub fn flush(&mut self) -> Result<(), String> {
+		if self.unsaved {
+			self.save()?;
+			self.unsaved = false;
+		}
+		Ok(())
+	}
+
+	/// Return the account of a username, whatever its case.
+	pub fn find(&self, username: &str) -> Option<&Account> {
+		self.accounts.iter().find(|account| account.username.eq_ignore_ascii_case(username))
+	}
+
+	/// Create the account or change its password.
+	pub fn set_password(&mut self, username: &str, password: &str) -> Result<(), String> {
+		self.set_password_hash(username, hash(password)?);
+		Ok(())
+	}
+
+	/// Create the account or change its password, already hashed.
+	pub fn set_password_hash(&mut self, username: &str, password_hash: String) {
+		match self.accounts.iter_mut().find(|account| account.username.eq_ignore_ascii_case(username)) {
+			Some(account) => account.password_hash = password_hash,
+			None => self.accounts.push(Account { username: username.to_string(), password_hash, role: Role::Player }),
+		}
+		self.unsaved = true;
+	}
+
+	/// The role of a username. Players without an account are simple players.
+	pub fn role(&self, username: &str) -> Role {
+		self.find(username).map(|account| account.role).unwrap_or_default()
+	}
+
+	/// Return false if there was no such account.
+	/// Roles can only be given to accounts, since anyone can join with the username of a guest.
+	pub fn set_role(&mut self, username: &str, role: Role) -> bool {
+		match self.accounts.iter_mut().find(|account| account.username.eq_ignore_ascii_case(username)) {
+			Some(account) => account.role = role,
+			None => return false,
+		}
+		self.unsaved = true;
+		true
+	}
+
+	/// Return false if there was no such account.
+	pub fn remove(&mut self, username: &str) -> bool {
+		let len = self.accounts.len();
+		self.accounts.retain(|account| !account.username.eq_ignore_ascii_case(username));
+		self.unsaved |= self.accounts.len() != len;
+		self.accounts.len() != len
+	}
+
+	/// Check the credentials sent by a player, except the password itself which is checked by the returned job.
+	/// Nothing is registered yet, see [`Login::Registered`].
+	pub fn login(&self, username: &str, password: Option<&str>, allow_registration: bool, require_password: bool) -> Result<(Login, Option<PasswordJob>), (KickReason, String)> {
+		match (self.find(username), password) {
+			(Some(account), Some(password)) => Ok((Login::Authenticated, Some(PasswordJob::Verify{password: password.to_string(), password_hash: account.password_hash.clone()}))),
+			(Some(_), None) => Err((KickReason::WrongPassword, String::from("wrong password"))),
+			(None, Some(password)) if allow_registration => Ok((Login::Registered, Some(PasswordJob::Hash{password: password.to_string()}))),
+			(None, Some(_)) => Err((KickReason::UnknownAccount, String::from("there is no such account and registration is disabled"))),
+			(None, None) if require_password => Err((KickReason::WrongPassword, String::from("a password is required to join this server"))),
+			(None, None) => Ok((Login::Guest, None)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_validate_username() {
+		let config = Config::default();
+		assert!(validate_username("Alice_42", &config).is_ok());
+		assert!(validate_username("", &config).is_err());
+		assert!(validate_username("Al", &config).is_err());
+		assert!(validate_username("A_very_long_username", &config).is_err());
+		assert!(validate_username("42", &config).is_err());
+		assert!(validate_username("Alice Bob", &config).is_err());
+		assert!(validate_username("Alice\x1B[31m", &config).is_err());
+		assert!(validate_username("Élodie", &config).is_err());
+		assert!(validate_username("SERVER", &config).is_err());
+	}
+
+	#[test]
+	fn test_login() {
+		let mut accounts = Accounts::default();
+		assert_eq!(accounts.login("Alice", None, true, false), Ok((Login::Guest, None)));
+		let (login, job) = accounts.login("Alice", Some("secret"), true, false).unwrap();
+		assert_eq!(login, Login::Registered);
+		assert!(accounts.find("alice").is_none());
+		accounts.set_password_hash("Alice", job.unwrap().run().unwrap().unwrap());
+		assert!(!accounts.find("alice").unwrap().password_hash.contains("secret"));
+		let (login, job) = accounts.login("ALICE", Some("secret"), true, false).unwrap();
+		assert_eq!(login, Login::Authenticated);
+		assert_eq!(job.unwrap().run(), Ok(None));
+		let (_, job) = accounts.login("Alice", Some("wrong"), true, false).unwrap();
+		assert_eq!(job.unwrap().run().unwrap_err().0, KickReason::WrongPassword);
+		assert_eq!(accounts.login("Alice", None, true, false).unwrap_err().0, KickReason::WrongPassword);
+
+		assert_eq!(accounts.login("Bob", Some("secret"), false, false).unwrap_err().0, KickReason::UnknownAccount);
+		assert_eq!(accounts.login("Bob", None, false, true).unwrap_err().0, KickReason::WrongPassword);
+
+		// the same password gives different hashes
+		accounts.set_password("Bob", "secret").unwrap();
+		assert_ne!(accounts.find("bob").unwrap().password_hash, accounts.find("alice").unwrap().password_hash);
+		assert_eq!(accounts.role("bob"), Role::Player);
+		assert!(accounts.set_role("BOB", Role::Moderator));
+		assert_eq!(accounts.role("bob"), Role::Moderator);
+		assert_eq!(accounts.set_password("bob", "other"), Ok(()));
+		assert_eq!(accounts.role("bob"), Role::Moderator);
+		assert!(!accounts.set_role("Carol", Role::Operator));
+		assert_eq!(accounts.role("carol"), Role::Player);
+		assert!(accounts.remove("bob"));
+		assert!(!accounts.remove("bob"));
+	}
+
+	#[test]
+	fn test_password_worker() {
+		let mut worker = PasswordWorker::default();
+		worker.start(1, PasswordJob::Hash{password: String::from("secret")});
+		worker.start(2, PasswordJob::Verify{password: String::from("secret"), password_hash: String::from("invalid")});
+		worker.wait();
+		let mut results = worker.poll();
+		results.sort_by_key(|(client_id, _)| *client_id);
+		assert_eq!(results.len(), 2);
+		let password_hash = results[0].1.clone().unwrap().unwrap();
+		assert_eq!(results[1].1.clone().unwrap_err().0, KickReason::WrongPassword);
+
+		worker.start(3, PasswordJob::Verify{password: String::from("secret"), password_hash});
+		worker.wait();
+		assert_eq!(worker.poll(), vec![(3, Ok(None))]);
+		assert!(worker.poll().is_empty());
+	}
+}
@@ -0,0 +1,241 @@
+//! Snapshots of the saved files, written as timestamped `.tar.gz` archives.
+//!
+//! Archives are written in a background thread. The game must not write the saved files meanwhile.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use chrono::Local;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+const EXTENSION: &str = ".tar.gz";
+
+/// A saved file or directory, and its name in the archives.
+pub type Source = (&'static str, PathBuf);
+
+pub struct Backups {
+	/// The backup being written, if any.
+	running: Option<JoinHandle<Result<String, String>>>,
+	pub last_backup: Instant,
+}
+
+impl Default for Backups {
+	fn default() -> Self {
+		Backups {
+			running: None,
+			last_backup: Instant::now(),
+		}
+	}
+}
+
+impl Backups {
+	pub fn is_running(&self) -> bool {
+		self.running.is_some()
+	}
+
+	/// Write a backup in a background thread, then remove the oldest backups to keep only `kept` of them.
+	pub fn start(&mut self, directory: PathBuf, sources: Vec<Source>, kept: usize) -> Result<(), String> {
+		if self.running.is_some() {
+			return Err(String::from("a backup is already being written"));
+		}
+		self.last_backup = Instant::now();
+		self.running = Some(thread::spawn(move || {
+			let name = create(&directory, &sources)?;
+			prune(&directory, kept)?;
+			Ok(name)
+		}));
+		Ok(())
+	}
+
+	/// Return the result of the running backup if it is over.
+	pub fn poll(&mut self) -> Option<Result<String, String>> {
+		if self.running.as_ref()?.is_finished() {
+			self.wait()
+		} else {
+			None
+		}
+	}
+
+	/// Wait for the running backup and return its result.
+	pub fn wait(&mut self) -> Option<Result<String, String>> {
+		let handle = self.running.take()?;
+		Some(handle.join().unwrap_or_else(|_| Err(String::from("the backup thread panicked"))))
+	}
+}
+
+/// Write an archive of the sources and return its name, which is the current time,
+/// followed by a counter if a backup already has this name.
+/// Missing sources are skipped.
+pub fn create(directory: &Path, sources: &[Source]) -> Result<String, String> {
+	fs::create_dir_all(directory).map_err(|error| format!("can't create the backup directory {}: {}", directory.display(), error))?;
+	let time = Local::now().format("%Y-%m-%d_%H-%M-%S-%3f").to_string();
+	let mut name = time.clone();
+	let mut counter = 1;
+	while directory.join(format!("{}{}", name, EXTENSION)).exists() {
+		counter += 1;
+		name = format!("{}_{}", time, counter);
+	}
+	let path = directory.join(format!("{}{}", name, EXTENSION));
+	let temporary_path = directory.join(format!("{}.tmp", name));
+
+	let write = || -> io::Result<()> {
+		let mut builder = tar::Builder::new(GzEncoder::new(File::create(&temporary_path)?, Compression::default()));
+		for (source_name, source_path) in sources {
+			if source_path.is_dir() {
+				builder.append_dir_all(source_name, source_path)?;
+			} else if source_path.is_file() {
+				builder.append_path_with_name(source_path, source_name)?;
+			}
+		}
+		builder.into_inner()?.finish()?;
+		fs::rename(&temporary_path, &path)
+	};
+	write().map_err(|error| {
+		let _ = fs::remove_file(&temporary_path);
+		format!("can't write the backup {}: {}", path.display(), error)
+	})?;
+	Ok(name)
+}
+
+/// Return the names of the available backups, from the oldest to the newest.
+pub fn list(directory: &Path) -> Result<Vec<String>, String> {
+	let entries = match fs::read_dir(directory) {
+		Ok(entries) => entries,
+		Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(error) => return Err(format!("can't read the backup directory {}: {}", directory.display(), error)),
+	};
+	let mut names: Vec<String> = entries
+		.filter_map(Result::ok)
+		.filter_map(|entry| entry.file_name().to_str()?.strip_suffix(EXTENSION).map(String::from))
+		.collect();
+	names.sort();
+	Ok(names)
+}
+
+/// Remove the oldest backups to keep only `kept` of them, and return how many have been removed.
+pub fn prune(directory: &Path, kept: usize) -> Result<usize, String> {
+	let names = list(directory)?;
+	let removed = names.len().saturating_sub(kept);
+	for name in &names[..removed] {
+		let path = directory.join(format!("{}{}", name, EXTENSION));
+		fs::remove_file(&path).map_err(|error| format!("can't remove the backup {}: {}", path.display(), error))?;
+	}
+	Ok(removed)
+}
+
+/// Replace the sources by their content in a backup.
+/// Sources which were missing when the backup was written are removed.
+pub fn restore(directory: &Path, name: &str, sources: &[Source]) -> Result<(), String> {
+	if !list(directory)?.iter().any(|backup| backup == name) {
+		return Err(format!("there is no backup named {}", name));
+	}
+	let path = directory.join(format!("{}{}", name, EXTENSION));
+	let unpacked = directory.join(format!("{}.restoring", name));
+	let _ = fs::remove_dir_all(&unpacked);
+
+	let file = File::open(&path).map_err(|error| format!("can't open {}: {}", path.display(), error))?;
+	tar::Archive::new(GzDecoder::new(file)).unpack(&unpacked).map_err(|error| format!("can't read {}: {}", path.display(), error))?;
+
+	// the current sources are kept aside until every source of the backup is in place, to be put back on errors
+	let previous = directory.join(format!("{}.previous", name));
+	let _ = fs::remove_dir_all(&previous);
+	fs::create_dir_all(&previous).map_err(|error| format!("can't create {}: {}", previous.display(), error))?;
+	let mut replaced = 0;
+	let mut result = Ok(());
+	for (source_name, source_path) in sources {
+		result = replace(source_path, &previous.join(source_name), &unpacked.join(source_name));
+		if result.is_err() {
+			break;
+		}
+		replaced += 1;
+	}
+
+	if let Err(error) = result {
+		for (i, (source_name, source_path)) in sources.iter().enumerate().take(replaced + 1) {
+			let previous_path = previous.join(source_name);
+			if previous_path.exists() || i < replaced {
+				let _ = remove(source_path);
+			}
+			if previous_path.exists() {
+				let _ = fs::rename(&previous_path, source_path);
+			}
+		}
+		let _ = fs::remove_dir_all(&unpacked);
+		return Err(format!("can't restore the backup {}: {}", name, error));
+	}
+	let _ = fs::remove_dir_all(&unpacked);
+	let _ = fs::remove_dir_all(&previous);
+	Ok(())
+}
+
+/// Move a source aside to `previous_path`, then move its version from a backup in its place.
+fn replace(source_path: &Path, previous_path: &Path, backup_path: &Path) -> io::Result<()> {
+	if source_path.exists() {
+		fs::rename(source_path, previous_path)?;
+	}
+	if backup_path.exists() {
+		fs::rename(backup_path, source_path)?;
+	}
+	Ok(())
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+	if path.is_dir() {
+		fs::remove_dir_all(path)
+	} else if path.exists() {
+		fs::remove_file(path)
+	} else {
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_create_and_restore() {
+		let root = std::env::temp_dir().join(format!("dungeon_backups_{}", std::process::id()));
+		let _ = fs::remove_dir_all(&root);
+		let backups = root.join("backups");
+		let sources: Vec<Source> = vec![("world", root.join("world")), ("bans.yml", root.join("bans.yml")), ("missing", root.join("missing"))];
+		fs::create_dir_all(root.join("world")).unwrap();
+		fs::write(root.join("world/r.0.0.region"), b"region").unwrap();
+		fs::write(root.join("bans.yml"), b"names: []").unwrap();
+
+		let name = create(&backups, &sources).unwrap();
+		assert_eq!(list(&backups).unwrap(), vec![name.clone()]);
+		// backups written in the same millisecond don't overwrite each other
+		let other = create(&backups, &sources).unwrap();
+		let another = create(&backups, &sources).unwrap();
+		assert_eq!(list(&backups).unwrap().len(), 3);
+		assert!(name < other && other < another);
+		fs::remove_file(backups.join(format!("{}{}", other, EXTENSION))).unwrap();
+		fs::remove_file(backups.join(format!("{}{}", another, EXTENSION))).unwrap();
+
+		// griefing
+		fs::write(root.join("world/r.0.0.region"), b"griefed").unwrap();
+		fs::write(root.join("world/r.0.1.region"), b"griefed").unwrap();
+		fs::write(root.join("missing"), b"created later").unwrap();
+
+		restore(&backups, &name, &sources).unwrap();
+		assert_eq!(fs::read(root.join("world/r.0.0.region")).unwrap(), b"region");
+		assert!(!root.join("world/r.0.1.region").exists());
+		assert_eq!(fs::read(root.join("bans.yml")).unwrap(), b"names: []");
+		assert!(!root.join("missing").exists());
+		assert_eq!(fs::read_dir(&backups).unwrap().count(), 1);
+		assert!(restore(&backups, "../world", &sources).is_err());
+
+		for old in ["2000-01-01_00-00-00", "2000-01-02_00-00-00"].iter() {
+			fs::copy(backups.join(format!("{}{}", name, EXTENSION)), backups.join(format!("{}{}", old, EXTENSION))).unwrap();
+		}
+		assert_eq!(prune(&backups, 2).unwrap(), 1);
+		assert_eq!(list(&backups).unwrap(), vec![String::from("2000-01-02_00-00-00"), name]);
+
+		fs::remove_dir_all(&root).unwrap();
+	}
+}
@@ -0,0 +1,215 @@
+//! Players and addresses not allowed to join the server.
+//!
+//! Addresses are banned by ranges written in the CIDR notation, such as `10.0.0.0/8`.
+//! A single address is a range containing only itself.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+
+/// A range of IP addresses sharing their first `prefix` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+	/// The first address of the range.
+	address: IpAddr,
+	prefix: u8,
+}
+
+/// IPv4 clients of dual-stack sockets have IPv6 addresses such as `::ffff:10.0.0.1`.
+fn canonical(address: IpAddr) -> IpAddr {
+	match address {
+		IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+		address => address,
+	}
+}
+
+/// The address with only its first `prefix` bits kept.
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+	match address {
+		// shifting by the whole width would overflow, so a zero prefix gives an empty mask instead
+		IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)).into()),
+		IpAddr::V6(v6) => IpAddr::V6((u128::from(v6) & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)).into()),
+	}
+}
+
+fn max_prefix(address: IpAddr) -> u8 {
+	match address {
+		IpAddr::V4(_) => 32,
+		IpAddr::V6(_) => 128,
+	}
+}
+
+impl IpRange {
+	pub fn contains(&self, address: IpAddr) -> bool {
+		let address = canonical(address);
+		address.is_ipv4() == self.address.is_ipv4() && mask(address, self.prefix) == self.address
+	}
+}
+
+impl From<IpAddr> for IpRange {
+	fn from(address: IpAddr) -> Self {
+		let address = canonical(address);
+		IpRange { address, prefix: max_prefix(address) }
+	}
+}
+
+impl FromStr for IpRange {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (address, prefix) = match s.split_once('/') {
+			Some((address, prefix)) => (address, Some(prefix)),
+			None => (s, None),
+		};
+		let address = canonical(address.parse().map_err(|_| "invalid IP address")?);
+		let prefix = match prefix {
+			Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max_prefix(address)).ok_or("invalid prefix length")?,
+			None => max_prefix(address),
+		};
+		Ok(IpRange { address: mask(address, prefix), prefix })
+	}
+}
+
+impl fmt::Display for IpRange {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		if self.prefix == max_prefix(self.address) {
+			write!(f, "{}", self.address)
+		} else {
+			write!(f, "{}/{}", self.address, self.prefix)
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ban {
+	/// A lowercase username or a range of IP addresses.
+	pub target: String,
+	pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BanList {
+	pub names: Vec<Ban>,
+	pub addresses: Vec<Ban>,
+	/// Where the list is saved. Nothing is saved when `None`.
+	#[serde(skip)]
+	path: Option<PathBuf>,
+	/// Whether the list has changed since it was saved.
+	#[serde(skip)]
+	unsaved: bool,
+}
+
+impl BanList {
+	/// Load the list saved at `path`, or create an empty one saved there.
+	pub fn load(path: impl AsRef<Path>) -> Result<BanList, String> {
+		let path = path.as_ref();
+		let mut bans = match fs::read_to_string(path) {
+			Ok(data) => serde_yaml::from_str(&data).map_err(|error| format!("invalid ban list {}: {}", path.display(), error))?,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => BanList::default(),
+			Err(error) => return Err(format!("can't read {}: {}", path.display(), error)),
+		};
+		bans.path = Some(path.to_path_buf());
+		Ok(bans)
+	}
+
+	pub fn save(&self) -> io::Result<()> {
+		if let Some(path) = &self.path {
+			let temporary_path = path.with_extension("tmp");
+			let data = serde_yaml::to_string(self).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+			fs::write(&temporary_path, data).and_then(|()| fs::rename(&temporary_path, path))?;
+		}
+		Ok(())
+	}
+
+	/// Save the list if it has changed.
+	pub fn flush(&mut self) -> io::Result<()> {
+		if self.unsaved {
+			self.save()?;
+			self.unsaved = false;
+		}
+		Ok(())
+	}
+
+	/// Return the ban matching a username, whatever its case.
+	pub fn find_name(&self, username: &str) -> Option<&Ban> {
+		let username = username.to_lowercase();
+		self.names.iter().find(|ban| ban.target == username)
+	}
+
+	/// Return a ban of a range containing the address.
+	pub fn find_address(&self, address: IpAddr) -> Option<&Ban> {
+		self.addresses.iter().find(|ban| ban.target.parse().map(|range: IpRange| range.contains(address)).unwrap_or(false))
+	}
+
+	/// Return false if the name was already banned.
+	pub fn ban_name(&mut self, username: &str, reason: String) -> bool {
+		if self.find_name(username).is_some() {
+			return false;
+		}
+		self.names.push(Ban { target: username.to_lowercase(), reason });
+		self.unsaved = true;
+		true
+	}
+
+	/// Return false if the range was already banned.
+	pub fn ban_address(&mut self, range: IpRange, reason: String) -> bool {
+		let range = range.to_string();
+		if self.addresses.iter().any(|ban| ban.target == range) {
+			return false;
+		}
+		self.addresses.push(Ban { target: range, reason });
+		self.unsaved = true;
+		true
+	}
+
+	/// Return false if the name was not banned.
+	pub fn unban_name(&mut self, username: &str) -> bool {
+		let username = username.to_lowercase();
+		let len = self.names.len();
+		self.names.retain(|ban| ban.target != username);
+		self.unsaved |= self.names.len() != len;
+		self.names.len() != len
+	}
+
+	/// Return false if the range was not banned.
+	/// Addresses banned by a larger range stay banned.
+	pub fn unban_address(&mut self, range: IpRange) -> bool {
+		let range = range.to_string();
+		let len = self.addresses.len();
+		self.addresses.retain(|ban| ban.target != range);
+		self.unsaved |= self.addresses.len() != len;
+		self.addresses.len() != len
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_ip_ranges() {
+		let range: IpRange = "10.1.2.3/16".parse().unwrap();
+		assert_eq!(range.to_string(), "10.1.0.0/16");
+		assert!(range.contains("10.1.200.7".parse().unwrap()));
+		assert!(range.contains("::ffff:10.1.0.1".parse().unwrap()));
+		assert!(!range.contains("10.2.0.1".parse().unwrap()));
+		assert!(!range.contains("::1".parse().unwrap()));
+		assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains("1.2.3.4".parse().unwrap()));
+		assert_eq!("2001:db8::1/32".parse::<IpRange>().unwrap().to_string(), "2001:db8::/32");
+		assert_eq!("127.0.0.1".parse::<IpRange>().unwrap().to_string(), "127.0.0.1");
+		assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+		assert!("10.0.0/8".parse::<IpRange>().is_err());
+
+		let mut bans = BanList::default();
+		assert!(bans.ban_address(range, String::from("spam")));
+		assert!(!bans.ban_address("10.1.0.0/16".parse().unwrap(), String::from("spam")));
+		assert_eq!(bans.find_address("10.1.9.9".parse().unwrap()).unwrap().reason, "spam");
+		assert!(!bans.unban_address("10.1.9.9".parse().unwrap()));
+		assert!(bans.unban_address(range));
+		assert!(bans.find_address("10.1.9.9".parse().unwrap()).is_none());
+	}
+}
@@ -0,0 +1,269 @@
+//! Chat channels, and the checks applied to the chat messages sent by players.
+//!
+//! Messages are routed by the game: a `receiver_id` of 0 is sent to the current channel of the sender,
+//! anything else is a whisper to that player. Messages with a `sender_id` of 0 come from the server itself.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use chrono::Local;
+use crate::log;
+
+/// A group of players receiving the same messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Channel {
+	/// Every player.
+	Global,
+	/// The players within the chat range of the sender.
+	Local,
+	/// The members of a party, designated by its lowercase name.
+	Party(String),
+	/// Moderators and operators.
+	Staff,
+}
+
+impl fmt::Display for Channel {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Channel::Global => write!(f, "global"),
+			Channel::Local => write!(f, "local"),
+			Channel::Party(name) => write!(f, "party {}", name),
+			Channel::Staff => write!(f, "staff"),
+		}
+	}
+}
+
+/// The channels of a player.
+#[derive(Debug, Clone)]
+pub struct ChatState {
+	/// The channels whose messages are received.
+	pub joined: Vec<Channel>,
+	/// Where the messages of the player are sent. Always joined.
+	pub current: Channel,
+	/// When the player sent its last message, used by the slow mode.
+	pub last_message: Option<Instant>,
+}
+
+impl Default for ChatState {
+	fn default() -> Self {
+		ChatState {
+			joined: vec![Channel::Global, Channel::Local],
+			current: Channel::Global,
+			last_message: None,
+		}
+	}
+}
+
+impl ChatState {
+	/// Join a channel and send the next messages there. A player can only be in one party.
+	pub fn join(&mut self, channel: Channel) {
+		if let Channel::Party(_) = channel {
+			self.joined.retain(|joined| !matches!(joined, Channel::Party(_)));
+		}
+		if !self.joined.contains(&channel) {
+			self.joined.push(channel.clone());
+		}
+		self.current = channel;
+	}
+
+	/// Stop receiving a channel. The next messages are sent to another joined channel.
+	pub fn leave(&mut self, channel: &Channel) -> Result<(), String> {
+		if !self.joined.contains(channel) {
+			return Err(format!("you are not in the {} channel", channel));
+		}
+		if self.joined.len() == 1 {
+			return Err(String::from("you can't leave your last channel"));
+		}
+		self.joined.retain(|joined| joined != channel);
+		if self.current == *channel {
+			self.current = self.joined[0].clone();
+		}
+		Ok(())
+	}
+
+	/// The channel of the party of the player, if it is in one.
+	pub fn party(&self) -> Option<&Channel> {
+		self.joined.iter().find(|channel| matches!(channel, Channel::Party(_)))
+	}
+}
+
+/// The chat messages written to a file with their time, for moderation.
+#[derive(Debug, Default)]
+pub struct ChatLog {
+	/// Nothing is written when `None`.
+	file: Option<File>,
+}
+
+impl ChatLog {
+	/// Append the messages to a file, creating it if needed.
+	pub fn open(path: impl AsRef<Path>) -> Result<ChatLog, String> {
+		let path = path.as_ref();
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.map_err(|error| format!("can't open the chat log {}: {}", path.display(), error))?;
+		Ok(ChatLog { file: Some(file) })
+	}
+
+	pub fn write(&mut self, line: &str) {
+		if let Some(file) = &mut self.file {
+			if let Err(error) = writeln!(file, "[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), line) {
+				log(format!("can't write the chat log: {}", error));
+			}
+		}
+	}
+}
+
+/// Replace the filtered words by asterisks, whatever their case.
+/// Only whole words are replaced, so that longer words containing them are kept.
+pub fn filter_words(message: &str, words: &[String]) -> String {
+	fn flush(filtered: &mut String, word: &mut String, words: &[String]) {
+		if words.iter().any(|banned| banned.to_lowercase() == word.to_lowercase()) {
+			filtered.extend(word.chars().map(|_| '*'));
+		} else {
+			filtered.push_str(word);
+		}
+		word.clear();
+	}
+
+	let mut filtered = String::with_capacity(message.len());
+	let mut word = String::new();
+	for c in message.chars() {
+		if c.is_alphanumeric() {
+			word.push(c);
+		} else {
+			flush(&mut filtered, &mut word, words);
+			filtered.push(c);
+		}
+	}
+	flush(&mut filtered, &mut word, words);
+	filtered
+}
+
+/// Parse a duration such as `30s`, `10m`, `2h` or `1d`. A bare number is in seconds.
+pub fn parse_duration(text: &str) -> Option<Duration> {
+	let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+		Some(idx) => text.split_at(idx),
+		None => (text, "s"),
+	};
+	let number: u64 = number.parse().ok()?;
+	let unit = match unit {
+		"s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		_ => return None,
+	};
+	number.checked_mul(unit).map(Duration::from_secs)
+}
+
+/// Format a duration such as `1h 5m 30s`, rounded up to the second.
+pub fn format_duration(duration: Duration) -> String {
+	let mut seconds = duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 };
+	let mut parts = Vec::new();
+	for (unit, length) in [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60)].iter() {
+		if seconds >= *length {
+			parts.push(format!("{}{}", seconds / length, unit));
+			seconds %= length;
+		}
+	}
+	if seconds > 0 || parts.is_empty() {
+		parts.push(format!("{}s", seconds));
+	}
+	parts.join(" ")
+}
+
+/// Characters which are invisible or change the direction of the text, and could be used to disguise a message.
+fn is_invisible(c: char) -> bool {
+	matches!(c, '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2069}' | '\u{FEFF}')
+}
+
+/// Remove the control and invisible characters and the surrounding whitespace of a message, then check its length.
+pub fn sanitize(message: &str, max_length: usize) -> Result<String, String> {
+	let message: String = message
+		.chars()
+		.map(|c| if c.is_whitespace() { ' ' } else { c })
+		.filter(|c| !c.is_control() && !is_invisible(*c))
+		.collect();
+	let message = message.trim();
+	if message.is_empty() {
+		return Err(String::from("your message is empty"));
+	}
+	if message.chars().count() > max_length {
+		return Err(format!("messages can't be longer than {} characters", max_length));
+	}
+	Ok(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sanitize() {
+		assert_eq!(sanitize("  hello  world ", 20), Ok(String::from("hello  world")));
+		assert_eq!(sanitize("line\nbreak\ttab", 20), Ok(String::from("line break tab")));
+		assert_eq!(sanitize("\x1B[31mred\x07", 20), Ok(String::from("[31mred")));
+		assert_eq!(sanitize("admin\u{202E}nimda", 20), Ok(String::from("adminnimda")));
+		assert_eq!(sanitize("héhé ✨", 6), Ok(String::from("héhé ✨")));
+		assert!(sanitize(" \u{200B}\n", 20).is_err());
+		assert!(sanitize("too long", 7).is_err());
+	}
+
+	#[test]
+	fn test_filter_words() {
+		let words = vec![String::from("heck"), String::from("Darn")];
+		assert_eq!(filter_words("Heck, darn it!", &words), "****, **** it!");
+		assert_eq!(filter_words("check the darnedest", &words), "check the darnedest");
+		assert_eq!(filter_words("hello", &[]), "hello");
+	}
+
+	#[test]
+	fn test_durations() {
+		assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+		assert_eq!(parse_duration("10m"), Some(Duration::from_secs(600)));
+		assert_eq!(parse_duration("1d"), Some(Duration::from_secs(86400)));
+		assert_eq!(parse_duration("2w"), None);
+		assert_eq!(parse_duration("m"), None);
+		assert_eq!(format_duration(Duration::from_secs(3930)), "1h 5m 30s");
+		assert_eq!(format_duration(Duration::from_millis(1500)), "2s");
+		assert_eq!(format_duration(Duration::from_secs(0)), "0s");
+	}
+
+	#[test]
+	fn test_channels() {
+		let mut state = ChatState::default();
+		state.join(Channel::Party(String::from("red")));
+		state.join(Channel::Party(String::from("blue")));
+		assert_eq!(state.party(), Some(&Channel::Party(String::from("blue"))));
+		assert_eq!(state.joined.len(), 3);
+
+		state.leave(&Channel::Party(String::from("blue"))).unwrap();
+		assert_eq!(state.current, Channel::Global);
+		assert!(state.leave(&Channel::Staff).is_err());
+		state.leave(&Channel::Global).unwrap();
+		assert_eq!(state.current, Channel::Local);
+		assert!(state.leave(&Channel::Local).is_err());
+	}
+
+	#[test]
+	fn test_chat_log() {
+		let path = std::env::temp_dir().join(format!("dungeon_chat_{}.log", std::process::id()));
+		let _ = std::fs::remove_file(&path);
+		ChatLog::default().write("not written");
+		let mut log = ChatLog::open(&path).unwrap();
+		log.write("[Alice] hello");
+		drop(log);
+		ChatLog::open(&path).unwrap().write("[Bob] hi");
+
+		let data = std::fs::read_to_string(&path).unwrap();
+		let lines: Vec<&str> = data.lines().collect();
+		assert_eq!(lines.len(), 2);
+		assert!(lines[0].starts_with('[') && lines[0].ends_with("] [Alice] hello"));
+		assert!(lines[1].ends_with("] [Bob] hi"));
+		std::fs::remove_file(&path).unwrap();
+	}
+}
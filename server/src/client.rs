@@ -0,0 +1,135 @@
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use protocol::message::{KickReason, Message};
+use crate::accounts::Login;
+use crate::chat::ChatState;
+use crate::config::Config;
+use crate::log;
+use crate::profiles::Profile;
+use crate::rate_limit::{RateLimiter, RateLimits};
+use crate::transport::{Connection, Disconnected};
+
+/// A count of misbehaviors. One of them is forgiven every time the client behaves for a while.
+#[derive(Debug, Clone, Default)]
+pub struct Warnings {
+	count: u16,
+	last: Option<Instant>,
+}
+
+impl Warnings {
+	/// Count a misbehavior, forgiving one previous warning for every `decay` elapsed since the last one.
+	/// Return the number of warnings.
+	pub fn add(&mut self, now: Instant, decay: Duration) -> u16 {
+		if let Some(last) = self.last {
+			let forgiven = now.saturating_duration_since(last).as_secs_f64() / decay.as_secs_f64();
+			self.count = self.count.saturating_sub(forgiven.min(u16::MAX as f64) as u16);
+		}
+		self.count = self.count.saturating_add(1);
+		self.last = Some(now);
+		self.count
+	}
+
+	pub fn count(&self) -> u16 {
+		self.count
+	}
+}
+
+pub struct Client {
+	pub username: String,
+	/// Invalid moves.
+	pub cheating_warns: Warnings,
+	/// Invalid and rate limited messages.
+	pub flooding_warns: Warnings,
+	pub id: u64,
+	pub connection: Box<dyn Connection>,
+	pub address: Option<IpAddr>,
+	pub loaded_chunks_top_left: (u64, u64),
+	pub loaded_entities: Vec<u64>,
+	/// The round-trip time measured by the last answered heartbeat.
+	pub latency: Option<Duration>,
+	/// The id and sending time of the heartbeat waiting for an answer.
+	pub pending_ping: Option<(u64, Instant)>,
+	pub last_ping: Instant,
+	pub last_message: Instant,
+	/// How the player is logging in, from its handshake until it joins.
+	pub pending_login: Option<Login>,
+	/// Loaded when the player joins.
+	pub profile: Option<Profile>,
+	pub rate_limiter: RateLimiter,
+	/// Since when too many messages are waiting to be sent to the client.
+	pub slow_since: Option<Instant>,
+	pub chat: ChatState,
+}
+
+impl Client {
+	pub fn new(id: u64, connection: Box<dyn Connection>, loaded_chunks_top_left: (u64, u64), rate_limits: &RateLimits) -> Self {
+		Client {
+			username: String::from("[undefined username]"),
+			cheating_warns: Warnings::default(),
+			flooding_warns: Warnings::default(),
+			id,
+			connection,
+			address: None,
+			loaded_chunks_top_left,
+			loaded_entities: Vec::new(),
+			latency: None,
+			pending_ping: None,
+			last_ping: Instant::now(),
+			last_message: Instant::now(),
+			pending_login: None,
+			profile: None,
+			rate_limiter: RateLimiter::new(rate_limits),
+			slow_since: None,
+			chat: ChatState::default(),
+		}
+	}
+
+	pub fn send(&mut self, message: &Message) -> Result<(), Disconnected> {
+		self.connection.send(message)
+	}
+
+	/// Count an invalid move, and kick the client for cheating once it has more than `max_warns` of them.
+	pub fn warn_cheating(&mut self, config: &Config, message: impl Into<String>) -> Result<(), Disconnected> {
+		if self.cheating_warns.add(Instant::now(), config.warn_decay()) > config.max_warns {
+			self.kick(KickReason::Cheating, message);
+			return Err(Disconnected);
+		}
+		Ok(())
+	}
+
+	/// Count an invalid or rate limited message, and kick the client for flooding once it has more than `max_warns` of them.
+	pub fn warn_flooding(&mut self, config: &Config, message: impl Into<String>) -> Result<(), Disconnected> {
+		if self.flooding_warns.add(Instant::now(), config.warn_decay()) > config.max_warns {
+			self.kick(KickReason::Flooding, message);
+			return Err(Disconnected);
+		}
+		Ok(())
+	}
+
+	/// Tell the client why it is being disconnected.
+	/// The connection is closed when the client is removed from the game.
+	pub fn kick(&mut self, reason: KickReason, message: impl Into<String>) {
+		let message = message.into();
+		log(format!("{} has been kicked ({}): {}", self.username, reason, message));
+		let _ = self.send(&Message::Kick{reason, message});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_warnings() {
+		let start = Instant::now();
+		let decay = Duration::from_secs(60);
+		let mut warnings = Warnings::default();
+		assert_eq!(warnings.add(start, decay), 1);
+		assert_eq!(warnings.add(start + Duration::from_secs(10), decay), 2);
+		assert_eq!(warnings.add(start + Duration::from_secs(20), decay), 3);
+
+		// one warning is forgiven for every quiet minute
+		assert_eq!(warnings.add(start + Duration::from_secs(90), decay), 3);
+		assert_eq!(warnings.add(start + Duration::from_secs(3600), decay), 1);
+	}
+}
@@ -0,0 +1,371 @@
+use protocol::message::{KickReason, Message};
+use crate::accounts;
+use crate::backups;
+use crate::game::Game;
+use crate::log;
+use super::*;
+
+pub const LIST: Command = Command {
+	name: "list",
+	args: &[],
+	role: Role::Moderator,
+	help: "list the connected players with their ping and position",
+	run: list,
+};
+
+pub const SAY: Command = Command {
+	name: "say",
+	args: &[Arg::new("message", ArgType::Text)],
+	role: Role::Moderator,
+	help: "send a message to every player",
+	run: say,
+};
+
+pub const KICK: Command = Command {
+	name: "kick",
+	args: &[Arg::new("player", ArgType::Player), Arg::optional("reason", ArgType::Text)],
+	role: Role::Moderator,
+	help: "disconnect a player",
+	run: kick,
+};
+
+pub const BAN: Command = Command {
+	name: "ban",
+	args: &[Arg::new("username", ArgType::Word), Arg::optional("reason", ArgType::Text)],
+	role: Role::Moderator,
+	help: "prevent a player from joining the server",
+	run: ban,
+};
+
+pub const UNBAN: Command = Command {
+	name: "unban",
+	args: &[Arg::new("username", ArgType::Word)],
+	role: Role::Moderator,
+	help: "allow a banned player to join the server again",
+	run: unban,
+};
+
+pub const BAN_IP: Command = Command {
+	name: "ban_ip",
+	args: &[Arg::new("address", ArgType::Address), Arg::optional("reason", ArgType::Text)],
+	role: Role::Operator,
+	help: "prevent any connection from an IP address or a range such as 10.0.0.0/8",
+	run: ban_ip,
+};
+
+pub const UNBAN_IP: Command = Command {
+	name: "unban_ip",
+	args: &[Arg::new("address", ArgType::Address)],
+	role: Role::Operator,
+	help: "allow connections from a banned IP address or range again",
+	run: unban_ip,
+};
+
+pub const OP: Command = Command {
+	name: "op",
+	args: &[Arg::new("username", ArgType::Word), Arg::optional("role", ArgType::Choice(&["moderator", "operator"]))],
+	role: Role::Operator,
+	help: "give a role to the owner of an account, operator by default",
+	run: op,
+};
+
+pub const DEOP: Command = Command {
+	name: "deop",
+	args: &[Arg::new("username", ArgType::Word)],
+	role: Role::Operator,
+	help: "make a moderator or an operator a simple player again",
+	run: deop,
+};
+
+pub const SAVE: Command = Command {
+	name: "save",
+	args: &[],
+	role: Role::Operator,
+	help: "save everything to the disk",
+	run: save,
+};
+
+pub const STOP: Command = Command {
+	name: "stop",
+	args: &[Arg::optional("message", ArgType::Text)],
+	role: Role::Operator,
+	help: "disconnect every player, save and stop the server",
+	run: stop,
+};
+
+pub const BACKUP: Command = Command {
+	name: "backup",
+	args: &[],
+	role: Role::Operator,
+	help: "save and write a backup of the world",
+	run: backup,
+};
+
+pub const RESTORE: Command = Command {
+	name: "restore",
+	args: &[Arg::optional("timestamp", ArgType::Word)],
+	role: Role::Operator,
+	help: "disconnect every player and restore a backup, or list the backups",
+	run: restore,
+};
+
+pub const WHITELIST: Command = Command {
+	name: "whitelist",
+	args: &[Arg::new("action", ArgType::Choice(&["on", "off", "list"]))],
+	role: Role::Operator,
+	help: "only allow the whitelisted players and the staff to join, or allow everyone again",
+	run: whitelist,
+};
+
+pub const WHITELIST_ADD: Command = Command {
+	name: "whitelist_add",
+	args: &[Arg::new("username", ArgType::Word)],
+	role: Role::Operator,
+	help: "allow a player to join when the whitelist is enabled",
+	run: whitelist_add,
+};
+
+pub const WHITELIST_REMOVE: Command = Command {
+	name: "whitelist_remove",
+	args: &[Arg::new("username", ArgType::Word)],
+	role: Role::Operator,
+	help: "remove a player from the whitelist",
+	run: whitelist_remove,
+};
+
+pub const SET_PASSWORD: Command = Command {
+	name: "set_password",
+	args: &[Arg::new("username", ArgType::Word), Arg::new("password", ArgType::Text)],
+	role: Role::Operator,
+	help: "create an account or change its password",
+	run: set_password,
+};
+
+pub const DELETE_ACCOUNT: Command = Command {
+	name: "delete_account",
+	args: &[Arg::new("username", ArgType::Word)],
+	role: Role::Operator,
+	help: "delete an account, so that anyone can use its username",
+	run: delete_account,
+};
+
+fn list(game: &mut Game, _sender: CommandSender, _args: &Args) -> CommandResult {
+	let mut list = format!("{} players connected", game.clients.iter().filter(|client| client.profile.is_some()).count());
+
+	for client in game.clients.iter() {
+		let address = match client.address {
+			Some(address) => address.to_string(),
+			None => String::from("unknown"),
+		};
+		// the username of a client which has not logged in yet is not checked
+		if client.profile.is_none() {
+			list.push_str(&format!("\nconnecting (id: {}, address: {})", client.id, address));
+			continue;
+		}
+		let latency = match client.latency {
+			Some(latency) => format!("{}ms", latency.as_millis()),
+			None => String::from("unknown"),
+		};
+		let position = match game.entities.get(&client.id) {
+			Some(entity) => format!("{} {}", entity.coords.x.main, entity.coords.y.main),
+			None => String::from("unknown"),
+		};
+		let sent = client.connection.bytes_sent() / 1024;
+		list.push_str(&format!("\n{} (id: {}, ping: {}, position: {}, address: {}, sent: {}KiB)", client.username, client.id, latency, position, address, sent));
+	}
+	Ok(list)
+}
+
+fn say(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let message = args.word(0).to_string();
+	let line = format!("[Server] {}", message);
+	log(&line);
+	game.chat_log.write(&line);
+	game.broadcast(&Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id: 0, receiver_name: String::new(), message});
+	Ok(String::from("message sent"))
+}
+
+fn kick(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let idx = game.client_index(args.player(0)).expect("the player is connected");
+	check_rank(game, sender, &game.clients[idx].username)?;
+	let reason = args.optional_word(1).unwrap_or("kicked by a moderator").to_string();
+	let client = game.kick(idx, KickReason::Kicked, reason);
+	Ok(format!("{} has been kicked", client.username))
+}
+
+fn ban(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	check_rank(game, sender, username)?;
+	let reason = args.optional_word(1).unwrap_or("banned by a moderator").to_string();
+	if !game.bans.ban_name(username, reason.clone()) {
+		return Err(format!("{} is already banned", username));
+	}
+
+	if let Some(idx) = game.clients.iter().position(|client| client.username.eq_ignore_ascii_case(username)) {
+		game.kick(idx, KickReason::Banned, reason);
+	}
+	Ok(format!("{} has been banned", username))
+}
+
+fn unban(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	if !game.bans.unban_name(username) {
+		return Err(format!("{} is not banned", username));
+	}
+	Ok(format!("{} has been unbanned", username))
+}
+
+fn ban_ip(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let range = args.address(0);
+	let reason = args.optional_word(1).unwrap_or("banned by a moderator").to_string();
+	if !game.bans.ban_address(range, reason.clone()) {
+		return Err(format!("{} is already banned", range));
+	}
+
+	let mut kicked = 0;
+	while let Some(idx) = game.clients.iter().position(|client| client.address.map(|address| range.contains(address)).unwrap_or(false)) {
+		game.kick(idx, KickReason::Banned, reason.clone());
+		kicked += 1;
+	}
+	Ok(format!("{} has been banned ({} players kicked)", range, kicked))
+}
+
+fn unban_ip(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let range = args.address(0);
+	if !game.bans.unban_address(range) {
+		return Err(format!("{} is not banned", range));
+	}
+	Ok(format!("{} has been unbanned", range))
+}
+
+/// Disconnect the players who could not join with the current whitelist, and return how many there were.
+fn kick_unwhitelisted(game: &mut Game) -> usize {
+	let mut kicked = 0;
+	while let Some(idx) = game.clients.iter().position(|client| {
+		client.profile.is_some() && !game.whitelist.contains(&client.username) && game.accounts.role(&client.username) < Role::Moderator
+	}) {
+		game.kick(idx, KickReason::NotWhitelisted, "you are not whitelisted on this server");
+		kicked += 1;
+	}
+	kicked
+}
+
+fn whitelist(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	match args.word(0) {
+		"list" => {
+			let state = if game.whitelist.enabled { "enabled" } else { "disabled" };
+			Ok(format!("the whitelist is {} and contains {} players: {}", state, game.whitelist.names.len(), game.whitelist.names.join(", ")))
+		},
+		action => {
+			let enabled = action == "on";
+			if game.whitelist.enabled == enabled {
+				return Err(format!("the whitelist is already {}", action));
+			}
+			game.whitelist.set_enabled(enabled);
+			if enabled {
+				let kicked = kick_unwhitelisted(game);
+				Ok(format!("the whitelist is enabled ({} players kicked)", kicked))
+			} else {
+				Ok(String::from("the whitelist is disabled"))
+			}
+		},
+	}
+}
+
+fn whitelist_add(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	if !game.whitelist.add(username) {
+		return Err(format!("{} is already whitelisted", username));
+	}
+	Ok(format!("{} has been whitelisted", username))
+}
+
+fn whitelist_remove(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	if !game.whitelist.remove(username) {
+		return Err(format!("{} is not whitelisted", username));
+	}
+	if game.whitelist.enabled {
+		kick_unwhitelisted(game);
+	}
+	Ok(format!("{} has been removed from the whitelist", username))
+}
+
+fn save(game: &mut Game, _sender: CommandSender, _args: &Args) -> CommandResult {
+	game.save()?;
+	Ok(String::from("saved"))
+}
+
+fn stop(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let message = args.optional_word(0).unwrap_or("the server is stopping");
+	game.stop(message)?;
+	Ok(String::from("the server is stopping"))
+}
+
+fn backup(game: &mut Game, _sender: CommandSender, _args: &Args) -> CommandResult {
+	game.start_backup()?;
+	Ok(String::from("the backup is being written"))
+}
+
+fn restore(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	match args.optional_word(0) {
+		Some(name) => {
+			game.restore(name)?;
+			Ok(format!("the backup {} has been restored", name))
+		},
+		None => {
+			let names = backups::list(&game.config.backup_path)?;
+			if names.is_empty() {
+				return Ok(String::from("no backup available"));
+			}
+			Ok(format!("available backups:\n{}", names.join("\n")))
+		},
+	}
+}
+
+fn set_password(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	accounts::validate_username(username, &game.config)?;
+	let created = game.accounts.find(username).is_none();
+	game.accounts.set_password(username, args.word(1))?;
+	if created {
+		Ok(format!("the account {} has been created", username))
+	} else {
+		Ok(format!("the password of {} has been changed", username))
+	}
+}
+
+fn delete_account(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	if !game.accounts.remove(username) {
+		return Err(format!("{} has no account", username));
+	}
+	Ok(format!("the account {} has been deleted", username))
+}
+
+fn set_role(game: &mut Game, username: &str, role: Role) -> CommandResult {
+	if game.accounts.role(username) == role {
+		return Err(format!("{} already has the role {}", username, role));
+	}
+	if !game.accounts.set_role(username, role) {
+		return Err(format!("{} has no account; roles can only be given to accounts", username));
+	}
+	log(format!("{} now has the role {}", username, role));
+	if let Some(idx) = game.clients.iter().position(|client| client.username.eq_ignore_ascii_case(username)) {
+		let id = game.clients[idx].id;
+		game.system_message(id, format!("your role is now {}", role));
+	}
+	Ok(format!("{} now has the role {}", username, role))
+}
+
+fn op(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let role = match args.optional_word(1) {
+		Some(role) => role.parse().expect("the role is a valid choice"),
+		None => Role::Operator,
+	};
+	set_role(game, args.word(0), role)
+}
+
+fn deop(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	set_role(game, args.word(0), Role::Player)
+}
@@ -0,0 +1,139 @@
+use std::time::Instant;
+use crate::chat::{format_duration, Channel};
+use crate::game::Game;
+use crate::log;
+use super::*;
+
+pub const JOIN: Command = Command {
+	name: "join",
+	args: &[
+		Arg::new("channel", ArgType::Choice(&["global", "local", "party", "staff"])),
+		Arg::optional("party", ArgType::Word),
+	],
+	role: Role::Player,
+	help: "receive the messages of a channel and send yours there",
+	run: join,
+};
+
+pub const LEAVE: Command = Command {
+	name: "leave",
+	args: &[Arg::new("channel", ArgType::Choice(&["global", "local", "party", "staff"]))],
+	role: Role::Player,
+	help: "stop receiving the messages of a channel",
+	run: leave,
+};
+
+pub const CHANNELS: Command = Command {
+	name: "channels",
+	args: &[],
+	role: Role::Player,
+	help: "list the channels you are in",
+	run: channels,
+};
+
+pub const MUTE: Command = Command {
+	name: "mute",
+	args: &[
+		Arg::new("username", ArgType::Word),
+		Arg::new("duration", ArgType::Duration),
+		Arg::optional("reason", ArgType::Text),
+	],
+	role: Role::Moderator,
+	help: "prevent a player from chatting for a while",
+	run: mute,
+};
+
+pub const UNMUTE: Command = Command {
+	name: "unmute",
+	args: &[Arg::new("username", ArgType::Word)],
+	role: Role::Moderator,
+	help: "allow a muted player to chat again",
+	run: unmute,
+};
+
+pub const SLOWMODE: Command = Command {
+	name: "slowmode",
+	args: &[Arg::new("delay", ArgType::Duration)],
+	role: Role::Moderator,
+	help: "set the time players must wait between two messages, 0 to disable it",
+	run: slowmode,
+};
+
+fn join(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let idx = player_index(game, sender)?;
+	let channel = match (args.word(0), args.optional_word(1)) {
+		("party", Some(name)) => Channel::Party(name.to_lowercase()),
+		("party", None) => return Err(String::from("missing argument <party>; usage: join party <name>")),
+		(_, Some(_)) => return Err(String::from("only parties have a name")),
+		("staff", None) if game.role_of(sender) < Role::Moderator => return Err(String::from("only moderators can join the staff channel")),
+		("staff", None) => Channel::Staff,
+		("local", None) => Channel::Local,
+		_ => Channel::Global,
+	};
+	game.clients[idx].chat.join(channel.clone());
+	Ok(format!("your messages are now sent to the {} channel", channel))
+}
+
+fn leave(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let idx = player_index(game, sender)?;
+	let state = &mut game.clients[idx].chat;
+	let channel = match args.word(0) {
+		"party" => state.party().cloned().ok_or_else(|| String::from("you are not in a party"))?,
+		"staff" => Channel::Staff,
+		"local" => Channel::Local,
+		_ => Channel::Global,
+	};
+	state.leave(&channel)?;
+	Ok(format!("you left the {} channel; your messages are sent to the {} channel", channel, state.current))
+}
+
+fn channels(game: &mut Game, sender: CommandSender, _args: &Args) -> CommandResult {
+	let idx = player_index(game, sender)?;
+	let state = &game.clients[idx].chat;
+	let joined: Vec<String> = state.joined.iter().map(|channel| channel.to_string()).collect();
+	Ok(format!("you are in: {}; your messages are sent to the {} channel", joined.join(", "), state.current))
+}
+
+fn mute(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	check_rank(game, sender, username)?;
+	let duration = args.duration(1);
+	game.mutes.insert(username.to_lowercase(), Instant::now() + duration);
+	log(format!("{} has been muted for {}", username, format_duration(duration)));
+
+	if let Some(idx) = game.find_player(username) {
+		let message = match args.optional_word(2) {
+			Some(reason) => format!("you have been muted for {}: {}", format_duration(duration), reason),
+			None => format!("you have been muted for {}", format_duration(duration)),
+		};
+		let id = game.clients[idx].id;
+		game.system_message(id, message);
+	}
+	Ok(format!("{} has been muted for {}", username, format_duration(duration)))
+}
+
+fn unmute(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let username = args.word(0);
+	match game.mutes.remove(&username.to_lowercase()) {
+		Some(until) if until > Instant::now() => {
+			log(format!("{} has been unmuted", username));
+			if let Some(idx) = game.find_player(username) {
+				let id = game.clients[idx].id;
+				game.system_message(id, "you can chat again");
+			}
+			Ok(format!("{} has been unmuted", username))
+		},
+		_ => Err(format!("{} is not muted", username)),
+	}
+}
+
+fn slowmode(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	game.slow_mode = args.duration(0);
+	if game.slow_mode == Duration::from_secs(0) {
+		log("slow mode disabled");
+		Ok(String::from("slow mode disabled"))
+	} else {
+		log(format!("slow mode set to {}", format_duration(game.slow_mode)));
+		Ok(format!("players must now wait {} between two messages", format_duration(game.slow_mode)))
+	}
+}
@@ -0,0 +1,26 @@
+use std::collections::hash_map::Entry;
+use crate::game::Game;
+use super::*;
+
+pub const TP: Command = Command {
+	name: "tp",
+	args: &[
+		Arg::new("id", ArgType::Integer),
+		Arg::new("x", ArgType::Integer),
+		Arg::new("y", ArgType::Integer),
+	],
+	role: Role::Moderator,
+	help: "teleport an entity where you want",
+	run: tp,
+};
+
+fn tp(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	if let Entry::Occupied(mut entity) = game.entities.entry(args.integer(0)) {
+		let entity = entity.get_mut();
+		entity.coords.x.main = args.integer(1);
+		entity.coords.y.main = args.integer(2);
+		Ok(String::from("entity has been teleported successfully"))
+	} else {
+		Err(String::from("entity does not exist. check existing entity with the command list"))
+	}
+}
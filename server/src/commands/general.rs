@@ -0,0 +1,27 @@
+use crate::game::Game;
+use super::*;
+
+pub const HELP: Command = Command {
+	name: "help",
+	args: &[Arg::optional("command", ArgType::Word)],
+	role: Role::Player,
+	help: "display the commands you can use, or the help of a command",
+	run: help,
+};
+
+fn help(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let role = game.role_of(sender);
+
+	if let Some(name) = args.optional_word(0) {
+		return match find(name) {
+			Some(command) if command.role <= role => Ok(format!("{} => {}", command.usage(), command.help)),
+			_ => Err(format!("unknow command: {}", name)),
+		};
+	}
+
+	let mut page = String::from("COMMANDS LIST:");
+	for command in COMMANDS.iter().filter(|command| command.role <= role) {
+		page.push_str(&format!("\n- {} => {}", command.usage(), command.help));
+	}
+	Ok(page)
+}
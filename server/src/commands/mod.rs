@@ -0,0 +1,369 @@
+//! Commands typed in the server console or sent in the chat by players.
+//!
+//! Every command declares its arguments, the role it requires and a help text.
+//! Parsing, usage and error messages are generated from these declarations.
+
+mod admin;
+mod chat;
+mod entities;
+mod general;
+mod player;
+mod world;
+
+use std::time::Duration;
+use protocol::block::{BlockCode, Orientation};
+use crate::accounts::Role;
+use crate::bans::IpRange;
+use crate::game::Game;
+
+/// Every available command.
+pub static COMMANDS: &[Command] = &[
+	general::HELP,
+	player::WHO,
+	player::PING,
+	player::MSG,
+	player::SPAWN,
+	admin::LIST,
+	admin::SAY,
+	admin::KICK,
+	admin::BAN,
+	admin::UNBAN,
+	admin::BAN_IP,
+	admin::UNBAN_IP,
+	admin::WHITELIST,
+	admin::WHITELIST_ADD,
+	admin::WHITELIST_REMOVE,
+	admin::SET_PASSWORD,
+	admin::DELETE_ACCOUNT,
+	admin::OP,
+	admin::DEOP,
+	admin::SAVE,
+	admin::BACKUP,
+	admin::RESTORE,
+	admin::STOP,
+	chat::JOIN,
+	chat::LEAVE,
+	chat::CHANNELS,
+	chat::MUTE,
+	chat::UNMUTE,
+	chat::SLOWMODE,
+	entities::TP,
+	world::SETBLOCK,
+	world::FILL,
+	world::REPLACE,
+	world::COPY,
+	world::PASTE,
+];
+
+/// Who is running a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommandSender {
+	Console,
+	/// A player, identified by its id.
+	Player(u64),
+}
+
+/// The type of an argument, used to parse it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgType {
+	/// A positive integer.
+	Integer,
+	/// A single word.
+	Word,
+	/// One of the listed words.
+	Choice(&'static [&'static str]),
+	/// A connected player, designated by its username or its id.
+	Player,
+	/// An IP address, or a range of addresses in the CIDR notation.
+	Address,
+	/// A block name such as `simple_wall`.
+	Block,
+	/// One of `up`, `down`, `left` and `right`.
+	Orientation,
+	/// A duration such as `30s`, `10m`, `2h` or `1d`.
+	Duration,
+	/// Everything until the end of the line. Must be the last argument.
+	Text,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Arg {
+	pub name: &'static str,
+	pub arg_type: ArgType,
+	pub optional: bool,
+}
+
+impl Arg {
+	pub const fn new(name: &'static str, arg_type: ArgType) -> Self {
+		Arg { name, arg_type, optional: false }
+	}
+
+	pub const fn optional(name: &'static str, arg_type: ArgType) -> Self {
+		Arg { name, arg_type, optional: true }
+	}
+}
+
+/// A parsed argument.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Integer(u64),
+	Word(String),
+	/// The id of a connected player.
+	Player(u64),
+	Address(IpRange),
+	Block(BlockCode),
+	Orientation(Orientation),
+	Duration(Duration),
+	Missing,
+}
+
+/// The parsed arguments of a command, in the declaration order.
+#[derive(Debug)]
+pub struct Args {
+	values: Vec<Value>,
+}
+
+impl Args {
+	/// Panics if the argument was not declared as an integer.
+	pub fn integer(&self, idx: usize) -> u64 {
+		match self.values[idx] {
+			Value::Integer(value) => value,
+			_ => panic!("argument {} is not an integer", idx),
+		}
+	}
+
+	/// Panics if the argument is missing or is not a word.
+	pub fn word(&self, idx: usize) -> &str {
+		self.optional_word(idx).unwrap_or_else(|| panic!("argument {} is missing", idx))
+	}
+
+	/// Return the id of a player. Panics if the argument was not declared as a player.
+	pub fn player(&self, idx: usize) -> u64 {
+		match self.values[idx] {
+			Value::Player(id) => id,
+			_ => panic!("argument {} is not a player", idx),
+		}
+	}
+
+	/// Panics if the argument was not declared as an address.
+	pub fn address(&self, idx: usize) -> IpRange {
+		match self.values[idx] {
+			Value::Address(address) => address,
+			_ => panic!("argument {} is not an address", idx),
+		}
+	}
+
+	/// Panics if the argument was not declared as a block.
+	pub fn block(&self, idx: usize) -> BlockCode {
+		match self.values[idx] {
+			Value::Block(block_code) => block_code,
+			_ => panic!("argument {} is not a block", idx),
+		}
+	}
+
+	/// Panics if the argument was not declared as a duration.
+	pub fn duration(&self, idx: usize) -> Duration {
+		match self.values[idx] {
+			Value::Duration(duration) => duration,
+			_ => panic!("argument {} is not a duration", idx),
+		}
+	}
+
+	pub fn optional_orientation(&self, idx: usize) -> Option<Orientation> {
+		match self.values.get(idx) {
+			Some(Value::Orientation(orientation)) => Some(*orientation),
+			_ => None,
+		}
+	}
+
+	pub fn optional_integer(&self, idx: usize) -> Option<u64> {
+		match self.values.get(idx) {
+			Some(Value::Integer(value)) => Some(*value),
+			_ => None,
+		}
+	}
+
+	pub fn optional_word(&self, idx: usize) -> Option<&str> {
+		match self.values.get(idx) {
+			Some(Value::Word(value)) => Some(value),
+			_ => None,
+		}
+	}
+}
+
+/// The result of a command: the text to send back to the sender.
+pub type CommandResult = Result<String, String>;
+
+pub struct Command {
+	pub name: &'static str,
+	pub args: &'static [Arg],
+	/// The lowest role allowed to run the command.
+	pub role: Role,
+	pub help: &'static str,
+	pub run: fn(&mut Game, CommandSender, &Args) -> CommandResult,
+}
+
+impl Command {
+	/// A usage line such as `tp <id> <x> <y>`.
+	pub fn usage(&self) -> String {
+		let mut usage = String::from(self.name);
+		for arg in self.args {
+			let name = match arg.arg_type {
+				ArgType::Choice(choices) => choices.join("|"),
+				ArgType::Text => format!("{}...", arg.name),
+				_ => arg.name.to_string(),
+			};
+			if arg.optional {
+				usage.push_str(&format!(" [{}]", name));
+			} else {
+				usage.push_str(&format!(" <{}>", name));
+			}
+		}
+		usage
+	}
+
+	/// Parse the arguments following the command name.
+	pub fn parse(&self, game: &Game, input: &str) -> Result<Args, String> {
+		let mut rest = input.trim();
+		let mut values = Vec::new();
+
+		for arg in self.args {
+			if rest.is_empty() {
+				if arg.optional {
+					values.push(Value::Missing);
+					continue;
+				}
+				return Err(format!("missing argument <{}>; usage: {}", arg.name, self.usage()));
+			}
+
+			let (word, remaining) = match arg.arg_type {
+				ArgType::Text => (rest, ""),
+				_ => match rest.find(char::is_whitespace) {
+					Some(end) => (&rest[..end], rest[end..].trim_start()),
+					None => (rest, ""),
+				},
+			};
+			rest = remaining;
+
+			let value = match arg.arg_type {
+				ArgType::Integer => match word.parse() {
+					Ok(value) => Value::Integer(value),
+					Err(_) => return Err(format!("<{}> must be a positive number, not {:?}", arg.name, word)),
+				},
+				ArgType::Choice(choices) if !choices.contains(&word) => {
+					return Err(format!("<{}> must be one of: {}", arg.name, choices.join(", ")));
+				},
+				ArgType::Player => match game.find_player(word) {
+					Some(idx) => Value::Player(game.clients[idx].id),
+					None => return Err(format!("no connected player is named {}", word)),
+				},
+				ArgType::Address => match word.parse() {
+					Ok(address) => Value::Address(address),
+					Err(_) => return Err(format!("<{}> must be an IP address or a range such as 10.0.0.0/8, not {:?}", arg.name, word)),
+				},
+				ArgType::Block => match word.parse() {
+					Ok(block_code) => Value::Block(block_code),
+					Err(_) => return Err(format!("<{}> must be one of: simple_slab, simple_wall", arg.name)),
+				},
+				ArgType::Orientation => match word.parse() {
+					Ok(orientation) => Value::Orientation(orientation),
+					Err(_) => return Err(format!("<{}> must be one of: up, down, left, right", arg.name)),
+				},
+				ArgType::Duration => match crate::chat::parse_duration(word) {
+					Some(duration) => Value::Duration(duration),
+					None => return Err(format!("<{}> must be a duration such as 30s, 10m, 2h or 1d, not {:?}", arg.name, word)),
+				},
+				_ => Value::Word(word.to_string()),
+			};
+			values.push(value);
+		}
+
+		if !rest.is_empty() {
+			return Err(format!("too many arguments; usage: {}", self.usage()));
+		}
+		Ok(Args { values })
+	}
+}
+
+/// The index of the client running the command.
+fn player_index(game: &Game, sender: CommandSender) -> Result<usize, String> {
+	match sender {
+		CommandSender::Player(id) => game.client_index(id).ok_or_else(|| String::from("you are not connected")),
+		CommandSender::Console => Err(String::from("only players can use this command")),
+	}
+}
+
+/// Moderators can only act on players whose role is below theirs, operators can act on anyone.
+fn check_rank(game: &Game, sender: CommandSender, username: &str) -> Result<(), String> {
+	let sender_role = game.role_of(sender);
+	let role = game.accounts.role(username);
+	if sender_role < Role::Operator && role >= sender_role {
+		return Err(format!("you can't do that to {}, whose role is {}", username, role));
+	}
+	Ok(())
+}
+
+pub fn find(name: &str) -> Option<&'static Command> {
+	COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Parse and run a command line, checking the role of the sender.
+pub fn execute(game: &mut Game, sender: CommandSender, line: &str) -> CommandResult {
+	let line = line.trim();
+	let (name, rest) = match line.find(char::is_whitespace) {
+		Some(end) => (&line[..end], &line[end..]),
+		None => (line, ""),
+	};
+	let command = match find(name) {
+		Some(command) => command,
+		None => return Err(String::from("unknow command; type help to get the full list of commands")),
+	};
+	if game.role_of(sender) < command.role {
+		return Err(format!("you don't have the permission to use {}", command.name));
+	}
+	let args = command.parse(game, rest)?;
+	(command.run)(game, sender, &args)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TEST: Command = Command {
+		name: "test",
+		args: &[
+			Arg::new("number", ArgType::Integer),
+			Arg::new("mode", ArgType::Choice(&["on", "off"])),
+			Arg::optional("message", ArgType::Text),
+		],
+		role: Role::Operator,
+		help: "a test command",
+		run: |_, _, _| Ok(String::new()),
+	};
+
+	#[test]
+	fn test_parse() {
+		let game = Game::new();
+		assert_eq!(TEST.usage(), "test <number> <on|off> [message...]");
+
+		let args = TEST.parse(&game, "42 on hello  world").unwrap();
+		assert_eq!(args.integer(0), 42);
+		assert_eq!(args.word(1), "on");
+		assert_eq!(args.optional_word(2), Some("hello  world"));
+
+		let args = TEST.parse(&game, " 7   off ").unwrap();
+		assert_eq!(args.integer(0), 7);
+		assert_eq!(args.optional_word(2), None);
+
+		assert!(TEST.parse(&game, "").unwrap_err().starts_with("missing argument <number>"));
+		assert!(TEST.parse(&game, "seven on").unwrap_err().starts_with("<number> must be a positive number"));
+		assert!(TEST.parse(&game, "7 maybe").unwrap_err().starts_with("<mode> must be one of"));
+	}
+
+	#[test]
+	fn test_unique_names() {
+		for (idx, command) in COMMANDS.iter().enumerate() {
+			assert!(COMMANDS[idx + 1..].iter().all(|other| other.name != command.name), "{} is registered twice", command.name);
+		}
+	}
+}
@@ -0,0 +1,80 @@
+use protocol::coords::Coords;
+use protocol::message::Message;
+use crate::game::Game;
+use super::*;
+
+pub const WHO: Command = Command {
+	name: "who",
+	args: &[],
+	role: Role::Player,
+	help: "list the players in the game",
+	run: who,
+};
+
+pub const PING: Command = Command {
+	name: "ping",
+	args: &[],
+	role: Role::Player,
+	help: "display the round-trip time between you and the server",
+	run: ping,
+};
+
+pub const MSG: Command = Command {
+	name: "msg",
+	args: &[Arg::new("player", ArgType::Player), Arg::new("message", ArgType::Text)],
+	role: Role::Player,
+	help: "send a private message to a player",
+	run: msg,
+};
+
+pub const SPAWN: Command = Command {
+	name: "spawn",
+	args: &[],
+	role: Role::Player,
+	help: "go back to the spawn",
+	run: spawn,
+};
+
+fn who(game: &mut Game, _sender: CommandSender, _args: &Args) -> CommandResult {
+	let names: Vec<&str> = game.clients.iter()
+		.filter(|client| client.profile.is_some())
+		.map(|client| client.username.as_str())
+		.collect();
+	match names.len() {
+		0 => Ok(String::from("nobody is playing")),
+		1 => Ok(format!("1 player online: {}", names[0])),
+		count => Ok(format!("{} players online: {}", count, names.join(", "))),
+	}
+}
+
+fn ping(game: &mut Game, sender: CommandSender, _args: &Args) -> CommandResult {
+	let idx = player_index(game, sender)?;
+	match game.clients[idx].latency {
+		Some(latency) => Ok(format!("your ping is {}ms", latency.as_millis())),
+		None => Ok(String::from("your ping has not been measured yet")),
+	}
+}
+
+fn msg(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let receiver_id = args.player(0);
+	let sender_id = match sender {
+		CommandSender::Player(id) => id,
+		CommandSender::Console => 0,
+	};
+	// the sender gets its whisper back, or an error
+	game.route_chat_message(sender_id, receiver_id, args.word(1).to_string());
+	match sender {
+		CommandSender::Player(_) => Ok(String::new()),
+		CommandSender::Console => Ok(String::from("message sent")),
+	}
+}
+
+fn spawn(game: &mut Game, sender: CommandSender, _args: &Args) -> CommandResult {
+	let idx = player_index(game, sender)?;
+	let id = game.clients[idx].id;
+	let entity = game.entities.get_mut(&id).ok_or_else(|| String::from("you are not in the game"))?;
+	entity.coords = Coords::default();
+	// the client only moves its player when told so
+	let _ = game.clients[idx].send(&Message::TpEntity{id, coords: Coords::default()});
+	Ok(String::from("you have been teleported to the spawn"))
+}
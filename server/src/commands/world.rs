@@ -0,0 +1,132 @@
+use protocol::block::{Block, Orientation};
+use crate::edit::{Area, Clipboard};
+use crate::game::Game;
+use super::*;
+
+pub const SETBLOCK: Command = Command {
+	name: "setblock",
+	args: &[
+		Arg::new("x", ArgType::Integer),
+		Arg::new("y", ArgType::Integer),
+		Arg::new("block", ArgType::Block),
+		Arg::optional("orientation", ArgType::Orientation),
+	],
+	role: Role::Operator,
+	help: "place a block",
+	run: setblock,
+};
+
+pub const FILL: Command = Command {
+	name: "fill",
+	args: &[
+		Arg::new("x1", ArgType::Integer),
+		Arg::new("y1", ArgType::Integer),
+		Arg::new("x2", ArgType::Integer),
+		Arg::new("y2", ArgType::Integer),
+		Arg::new("block", ArgType::Block),
+		Arg::optional("orientation", ArgType::Orientation),
+	],
+	role: Role::Operator,
+	help: "fill a rectangle with a block",
+	run: fill,
+};
+
+pub const REPLACE: Command = Command {
+	name: "replace",
+	args: &[
+		Arg::new("x1", ArgType::Integer),
+		Arg::new("y1", ArgType::Integer),
+		Arg::new("x2", ArgType::Integer),
+		Arg::new("y2", ArgType::Integer),
+		Arg::new("from", ArgType::Block),
+		Arg::new("to", ArgType::Block),
+	],
+	role: Role::Operator,
+	help: "replace a block by another in a rectangle, keeping orientations",
+	run: replace,
+};
+
+pub const COPY: Command = Command {
+	name: "copy",
+	args: &[
+		Arg::new("x1", ArgType::Integer),
+		Arg::new("y1", ArgType::Integer),
+		Arg::new("x2", ArgType::Integer),
+		Arg::new("y2", ArgType::Integer),
+	],
+	role: Role::Operator,
+	help: "copy a rectangle to your clipboard",
+	run: copy,
+};
+
+pub const PASTE: Command = Command {
+	name: "paste",
+	args: &[
+		Arg::new("x", ArgType::Integer),
+		Arg::new("y", ArgType::Integer),
+		Arg::optional("rotation", ArgType::Choice(&["0", "90", "180", "270"])),
+	],
+	role: Role::Operator,
+	help: "paste your clipboard with its top left corner at x y, turned clockwise",
+	run: paste,
+};
+
+fn area(args: &Args) -> Result<Area, String> {
+	Area::between((args.integer(0), args.integer(1)), (args.integer(2), args.integer(3)))
+}
+
+fn setblock(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let orientation = args.optional_orientation(3).unwrap_or(Orientation::Up);
+	game.set_block((args.integer(0), args.integer(1)), Block::new(args.block(2), orientation));
+	Ok(String::from("block placed"))
+}
+
+fn fill(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let area = area(args)?;
+	let block = Block::new(args.block(4), args.optional_orientation(5).unwrap_or(Orientation::Up));
+	let modified = area.positions().filter(|position| game.set_block(*position, block)).count();
+	Ok(format!("{} blocks modified", modified))
+}
+
+fn replace(game: &mut Game, _sender: CommandSender, args: &Args) -> CommandResult {
+	let area = area(args)?;
+	let (from, to) = (args.block(4), args.block(5));
+	let mut modified = 0;
+	for position in area.positions() {
+		let mut block = game.world.block(position);
+		if block.get_block_code() == from {
+			block.set_block_code(to);
+			if game.set_block(position, block) {
+				modified += 1;
+			}
+		}
+	}
+	Ok(format!("{} blocks modified", modified))
+}
+
+fn copy(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let area = area(args)?;
+	game.clipboards.insert(sender, Clipboard::copy(&mut game.world, area));
+	Ok(format!("{} blocks copied", area.block_count()))
+}
+
+fn paste(game: &mut Game, sender: CommandSender, args: &Args) -> CommandResult {
+	let clipboard = match game.clipboards.get(&sender) {
+		Some(clipboard) => clipboard.rotated(args.optional_word(2).map(|rotation| rotation.parse().unwrap()).unwrap_or(0)),
+		None => return Err(String::from("your clipboard is empty; use copy first")),
+	};
+	let (x, y) = (args.integer(0), args.integer(1));
+	if x.checked_add(clipboard.width).is_none() || y.checked_add(clipboard.height).is_none() {
+		return Err(String::from("the clipboard does not fit there"));
+	}
+
+	let mut modified = 0;
+	for j in 0..clipboard.height {
+		for i in 0..clipboard.width {
+			if game.set_block((x + i, y + j), clipboard.get(i, j)) {
+				modified += 1;
+			}
+		}
+	}
+	Ok(format!("{} blocks modified", modified))
+}
@@ -0,0 +1,329 @@
+//! Server settings, read from a YAML file and overridden by command-line flags.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use protocol::coords::Coords;
+use serde::{Serialize, Deserialize};
+use crate::rate_limit::RateLimits;
+use crate::tick::MAX_TICK_RATE;
+use crate::transport::{DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_MAX_QUEUED_BYTES};
+
+/// The file read when no `--config` flag is given. It is not required to exist.
+pub const DEFAULT_CONFIG_PATH: &str = "server.yml";
+
+/// The largest view, in chunks. Every chunk of the view is sent to a player when it joins.
+pub const MAX_VIEW_SIZE: u64 = 64;
+
+pub const USAGE: &str = "USAGE:\n\
+	- server [OPTIONS] => run the server\n\
+	- server migrate-world <path> => upgrade a world save written by an older version\n\
+	\n\
+	OPTIONS:\n\
+	- --config <path> => read settings from this file (default: server.yml)\n\
+	- --bind <address> => the address to listen on\n\
+	- --port <port> => the port to listen on\n\
+	- --tick-rate <ticks per second> => how many ticks are run every second\n\
+	- --max-warns <number> => how many invalid moves, and how many invalid or rate limited messages, are tolerated before a kick\n\
+	- --view-width <chunks> => how many chunks a player can see horizontally\n\
+	- --view-height <chunks> => how many chunks a player can see vertically\n\
+	- --world <path> => the directory of the world save\n\
+	- --name <name> => the name of the server shown to players\n\
+	- --motd <message> => the message of the day\n\
+	- --help => display this page";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+	pub bind_address: String,
+	pub port: u16,
+	/// Ticks per second.
+	pub tick_rate: u32,
+	/// Invalid moves tolerated before a player is kicked for cheating.
+	/// Invalid and rate limited messages are counted separately, with the same limit.
+	pub max_warns: u16,
+	/// Seconds without misbehaving after which a warning is forgiven.
+	pub warn_decay: f64,
+	/// In bytes. Clients sending larger messages are disconnected.
+	pub max_message_size: usize,
+	/// Messages of a client processed during a tick. The others wait for the next ticks.
+	pub max_messages_per_tick: usize,
+	pub rate_limits: RateLimits,
+	/// In bytes. Clients are disconnected at once when more messages are waiting to be sent to them.
+	pub max_queued_bytes: usize,
+	/// In bytes. Clients with more messages waiting to be sent are slow.
+	pub slow_client_bytes: usize,
+	/// Seconds a client can stay slow before being disconnected.
+	pub slow_client_timeout: f64,
+	/// Width of the area sent to players, in chunks.
+	pub view_width: u64,
+	/// Height of the area sent to players, in chunks.
+	pub view_height: u64,
+	/// Seconds between two heartbeats.
+	pub heartbeat_interval: f64,
+	/// Seconds of silence after which a client is disconnected.
+	pub timeout: f64,
+	pub world_path: PathBuf,
+	/// Seconds between two saves of the world.
+	pub autosave_interval: f64,
+	/// Seconds after which the regions no player can see are removed from memory.
+	pub unload_delay: f64,
+	/// The name of the server shown to players.
+	pub server_name: String,
+	pub motd: String,
+	/// In characters.
+	pub max_chat_length: usize,
+	/// Distance in blocks within which the messages of the local channel are received.
+	pub chat_range: f64,
+	/// Words replaced by asterisks in chat messages, whatever their case.
+	pub word_filter: Vec<String>,
+	/// The file where chat messages and whispers are appended.
+	pub chat_log_path: PathBuf,
+	pub bans_path: PathBuf,
+	pub whitelist_path: PathBuf,
+	pub min_username_length: usize,
+	pub max_username_length: usize,
+	/// Usernames nobody can use, whatever their case.
+	pub reserved_names: Vec<String>,
+	pub accounts_path: PathBuf,
+	/// Create an account when a player sends a password for a username without account.
+	pub allow_registration: bool,
+	/// Refuse the players without account.
+	pub require_password: bool,
+	/// The directory of the player profiles.
+	pub players_path: PathBuf,
+	/// The directory where backups are written.
+	pub backup_path: PathBuf,
+	/// Seconds between two backups. Backups are only made by the `backup` command when 0.
+	pub backup_interval: f64,
+	/// How many backups are kept before the oldest ones are removed.
+	pub backups_kept: usize,
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Config {
+			bind_address: String::from("localhost"),
+			port: 51034,
+			tick_rate: 60,
+			max_warns: 5,
+			warn_decay: 60.0,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+			max_messages_per_tick: 64,
+			rate_limits: RateLimits::default(),
+			max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+			slow_client_bytes: 1024 * 1024,
+			slow_client_timeout: 10.0,
+			view_width: 8,
+			view_height: 4,
+			heartbeat_interval: 5.0,
+			timeout: 30.0,
+			world_path: PathBuf::from("world"),
+			autosave_interval: 60.0,
+			unload_delay: 30.0,
+			server_name: String::from("Dungeon"),
+			motd: String::from("Welcome to the dungeon!"),
+			max_chat_length: 256,
+			chat_range: 32.0,
+			word_filter: Vec::new(),
+			chat_log_path: PathBuf::from("chat.log"),
+			bans_path: PathBuf::from("bans.yml"),
+			whitelist_path: PathBuf::from("whitelist.yml"),
+			min_username_length: 3,
+			max_username_length: 16,
+			reserved_names: ["server", "console", "admin", "operator", "moderator", "undefined"].iter().map(|name| name.to_string()).collect(),
+			accounts_path: PathBuf::from("accounts.yml"),
+			allow_registration: true,
+			require_password: false,
+			players_path: PathBuf::from("players"),
+			backup_path: PathBuf::from("backups"),
+			backup_interval: 3600.0,
+			backups_kept: 24,
+		}
+	}
+}
+
+impl Config {
+	/// Read a config file.
+	pub fn from_file(path: impl AsRef<Path>) -> Result<Config, String> {
+		let path = path.as_ref();
+		let data = fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path.display(), error))?;
+		let config: Config = serde_yaml::from_str(&data).map_err(|error| format!("invalid config file {}: {}", path.display(), error))?;
+		config.validate()?;
+		Ok(config)
+	}
+
+	/// Build the config from the command-line arguments (without the program name).
+	///
+	/// The config file is read first, then every flag overrides the matching setting.
+	/// Returns `Ok(None)` when the user only asked for help.
+	pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Config>, String> {
+		let args: Vec<String> = args.into_iter().collect();
+		let mut flags: Vec<(&str, &str)> = Vec::new();
+		let mut config_path: Option<&str> = None;
+
+		let mut idx = 0;
+		while idx < args.len() {
+			let flag = args[idx].as_str();
+			if flag == "--help" || flag == "-h" {
+				return Ok(None);
+			}
+			let value = args.get(idx + 1).ok_or_else(|| format!("{} requires a value", flag))?;
+			if flag == "--config" {
+				config_path = Some(value);
+			} else {
+				flags.push((flag, value));
+			}
+			idx += 2;
+		}
+
+		let mut config = match config_path {
+			Some(path) => Config::from_file(path)?,
+			None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::from_file(DEFAULT_CONFIG_PATH)?,
+			None => Config::default(),
+		};
+		for (flag, value) in flags {
+			config.set(flag, value)?;
+		}
+		config.validate()?;
+
+		Ok(Some(config))
+	}
+
+	fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+		fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+			value.parse().map_err(|_| format!("invalid value for {}: {}", flag, value))
+		}
+
+		match flag {
+			"--bind" => self.bind_address = value.to_string(),
+			"--port" => self.port = parse(flag, value)?,
+			"--tick-rate" => self.tick_rate = parse(flag, value)?,
+			"--max-warns" => self.max_warns = parse(flag, value)?,
+			"--view-width" => self.view_width = parse(flag, value)?,
+			"--view-height" => self.view_height = parse(flag, value)?,
+			"--world" => self.world_path = PathBuf::from(value),
+			"--name" => self.server_name = value.to_string(),
+			"--motd" => self.motd = value.to_string(),
+			flag => return Err(format!("unknown option: {}", flag)),
+		}
+		Ok(())
+	}
+
+	fn validate(&self) -> Result<(), String> {
+		if self.tick_rate == 0 || self.tick_rate > MAX_TICK_RATE {
+			return Err(format!("tick_rate must be between 1 and {}", MAX_TICK_RATE));
+		}
+		if self.min_username_length == 0 || self.min_username_length > self.max_username_length {
+			return Err(String::from("min_username_length must be positive and less than max_username_length"));
+		}
+		if self.max_chat_length == 0 {
+			return Err(String::from("max_chat_length must be positive"));
+		}
+		if self.max_message_size == 0 || self.max_messages_per_tick == 0 {
+			return Err(String::from("max_message_size and max_messages_per_tick must be positive"));
+		}
+		self.rate_limits.validate()?;
+		if self.slow_client_bytes > self.max_queued_bytes {
+			return Err(String::from("slow_client_bytes must not be greater than max_queued_bytes"));
+		}
+		if self.view_width == 0 || self.view_height == 0 || self.view_width > MAX_VIEW_SIZE || self.view_height > MAX_VIEW_SIZE {
+			return Err(format!("view_width and view_height must be between 1 and {}", MAX_VIEW_SIZE));
+		}
+		if !(self.heartbeat_interval >= 0.0 && self.timeout > 0.0 && self.autosave_interval > 0.0 && self.unload_delay >= 0.0 && self.backup_interval >= 0.0 && self.slow_client_timeout >= 0.0 && self.chat_range > 0.0 && self.warn_decay > 0.0) {
+			return Err(String::from("heartbeat_interval, timeout, autosave_interval, unload_delay, backup_interval, slow_client_timeout, chat_range and warn_decay must be positive"));
+		}
+		Ok(())
+	}
+
+	/// The top left corner of the chunks seen by a player standing at `coords`.
+	/// The view is kept inside the world when the player stands near its edges.
+	pub fn view_top_left(&self, coords: &Coords) -> (u64, u64) {
+		let (x, y) = (coords.x.main - coords.x.main % 8, coords.y.main - coords.y.main % 8);
+		// the last chunk of the world starts at u64::MAX - 7
+		let (last_x, last_y) = (u64::MAX - 7 - (self.view_width - 1) * 8, u64::MAX - 7 - (self.view_height - 1) * 8);
+		(x.saturating_sub(self.view_width / 2 * 8).min(last_x), y.saturating_sub(self.view_height / 2 * 8).min(last_y))
+	}
+
+	pub fn heartbeat_interval(&self) -> Duration {
+		Duration::from_secs_f64(self.heartbeat_interval)
+	}
+
+	pub fn timeout(&self) -> Duration {
+		Duration::from_secs_f64(self.timeout)
+	}
+
+	pub fn autosave_interval(&self) -> Duration {
+		Duration::from_secs_f64(self.autosave_interval)
+	}
+
+	pub fn slow_client_timeout(&self) -> Duration {
+		Duration::from_secs_f64(self.slow_client_timeout)
+	}
+
+	pub fn warn_decay(&self) -> Duration {
+		Duration::from_secs_f64(self.warn_decay)
+	}
+
+	pub fn unload_delay(&self) -> Duration {
+		Duration::from_secs_f64(self.unload_delay)
+	}
+
+	pub fn backup_interval(&self) -> Option<Duration> {
+		if self.backup_interval > 0.0 {
+			Some(Duration::from_secs_f64(self.backup_interval))
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use protocol::coords::SingleAxis;
+
+	fn args(args: &[&str]) -> Vec<String> {
+		args.iter().map(|arg| arg.to_string()).collect()
+	}
+
+	#[test]
+	fn test_file_and_flags() {
+		let path = std::env::temp_dir().join(format!("dungeon_config_{}.yml", std::process::id()));
+		fs::write(&path, "port: 4000\nmotd: from the file\nview_width: 10\n").unwrap();
+
+		let config = Config::from_args(args(&["--config", path.to_str().unwrap(), "--motd", "from the flags", "--tick-rate", "20"])).unwrap().unwrap();
+		fs::remove_file(&path).unwrap();
+
+		assert_eq!(config.port, 4000);
+		assert_eq!(config.view_width, 10);
+		assert_eq!(config.motd, "from the flags");
+		assert_eq!(config.tick_rate, 20);
+		assert_eq!(config.max_warns, Config::default().max_warns);
+	}
+
+	#[test]
+	fn test_view_near_edges() {
+		let config = Config::default();
+		let at = |x, y| Coords::new(SingleAxis::new(x, 0), SingleAxis::new(y, 0));
+		let center = u64::MAX / 2 + 1;
+		assert_eq!(config.view_top_left(&at(center + 3, center)), (center - 32, center - 16));
+		assert_eq!(config.view_top_left(&at(0, 5)), (0, 0));
+		assert_eq!(config.view_top_left(&at(20, 9)), (0, 0));
+		assert_eq!(config.view_top_left(&at(u64::MAX, u64::MAX)), (u64::MAX - 63, u64::MAX - 31));
+	}
+
+	#[test]
+	fn test_invalid_args() {
+		assert!(Config::from_args(args(&["--port"])).is_err());
+		assert!(Config::from_args(args(&["--port", "not a number"])).is_err());
+		assert!(Config::from_args(args(&["--tick-rate", "0"])).is_err());
+		assert!(Config::from_args(args(&["--tick-rate", "4000000000"])).is_err());
+		assert!(Config::from_args(args(&["--view-width", "0"])).is_err());
+		assert!(Config::from_args(args(&["--view-height", "65"])).is_err());
+		assert!(Config::from_args(args(&["--view-width", "64", "--view-height", "1"])).unwrap().is_some());
+		assert!(Config::from_args(args(&["--unknown", "1"])).is_err());
+		assert!(Config::from_args(args(&["--config", "/does/not/exist.yml"])).is_err());
+		assert_eq!(Config::from_args(args(&["--help"])), Ok(None));
+	}
+}
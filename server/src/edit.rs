@@ -0,0 +1,126 @@
+//! Helpers for the world editing commands.
+
+use protocol::block::Block;
+use crate::world::World;
+
+/// The maximum number of blocks a single command can modify.
+pub const MAX_EDITED_BLOCKS: u64 = 65_536;
+
+/// A rectangle of blocks, both corners included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Area {
+	pub left: u64,
+	pub top: u64,
+	pub width: u64,
+	pub height: u64,
+}
+
+impl Area {
+	/// Build the area between two opposite corners, given in any order.
+	pub fn between((x1, y1): (u64, u64), (x2, y2): (u64, u64)) -> Result<Area, String> {
+		let area = Area {
+			left: x1.min(x2),
+			top: y1.min(y2),
+			width: x1.max(x2) - x1.min(x2) + 1,
+			height: y1.max(y2) - y1.min(y2) + 1,
+		};
+		if area.width.saturating_mul(area.height) > MAX_EDITED_BLOCKS {
+			return Err(format!("the area is too big ({}x{} blocks, the limit is {} blocks)", area.width, area.height, MAX_EDITED_BLOCKS));
+		}
+		Ok(area)
+	}
+
+	/// The number of blocks in the area.
+	pub fn block_count(&self) -> u64 {
+		self.width * self.height
+	}
+
+	/// Every position in the area, line by line.
+	pub fn positions(self) -> impl Iterator<Item = (u64, u64)> {
+		(0..self.height).flat_map(move |j| (0..self.width).map(move |i| (self.left + i, self.top + j)))
+	}
+}
+
+/// Blocks copied by the `copy` command, waiting to be pasted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clipboard {
+	pub width: u64,
+	pub height: u64,
+	/// The blocks, line by line.
+	pub blocks: Vec<Block>,
+}
+
+impl Clipboard {
+	pub fn copy(world: &mut World, area: Area) -> Clipboard {
+		Clipboard {
+			width: area.width,
+			height: area.height,
+			blocks: area.positions().map(|position| world.block(position)).collect(),
+		}
+	}
+
+	pub fn get(&self, x: u64, y: u64) -> Block {
+		self.blocks[(y * self.width + x) as usize]
+	}
+
+	/// Turn the content a quarter turn clockwise, as seen on the screen, orientations included.
+	pub fn rotate_clockwise(&self) -> Clipboard {
+		let mut blocks = Vec::with_capacity(self.blocks.len());
+		for y in 0..self.width {
+			for x in 0..self.height {
+				let mut block = self.get(y, self.height - 1 - x);
+				block.set_orientation(block.get_orientation().rotate_clockwise());
+				blocks.push(block);
+			}
+		}
+		Clipboard {
+			width: self.height,
+			height: self.width,
+			blocks,
+		}
+	}
+
+	/// Turn the content clockwise by a multiple of 90 degrees.
+	pub fn rotated(&self, degrees: u64) -> Clipboard {
+		let mut clipboard = self.clone();
+		for _ in 0..(degrees / 90) % 4 {
+			clipboard = clipboard.rotate_clockwise();
+		}
+		clipboard
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use protocol::block::{BlockCode, Orientation};
+
+	#[test]
+	fn test_rotate() {
+		let wall = Block::new(BlockCode::SimpleWall, Orientation::Up);
+		let slab = Block::new(BlockCode::SimpleSlab, Orientation::Up);
+		// a wall in the top right corner of a 3x2 area
+		let clipboard = Clipboard {
+			width: 3,
+			height: 2,
+			blocks: vec![slab, slab, wall, slab, slab, slab],
+		};
+
+		let rotated = clipboard.rotated(90);
+		assert_eq!((rotated.width, rotated.height), (2, 3));
+		assert_eq!(rotated.get(1, 2), Block::new(BlockCode::SimpleWall, Orientation::Right));
+		assert_eq!(rotated.get(0, 0), Block::new(BlockCode::SimpleSlab, Orientation::Right));
+
+		let rotated = clipboard.rotated(180);
+		assert_eq!(rotated.get(0, 1), Block::new(BlockCode::SimpleWall, Orientation::Down));
+		assert_eq!(clipboard.rotated(360), clipboard);
+	}
+
+	#[test]
+	fn test_area() {
+		let area = Area::between((10, 4), (8, 6)).unwrap();
+		assert_eq!(area, Area { left: 8, top: 4, width: 3, height: 3 });
+		assert_eq!(area.positions().count(), 9);
+		assert!(Area::between((0, 0), (1000, 1000)).is_err());
+	}
+}
@@ -0,0 +1,1616 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use protocol::message::{KickReason, Message, PROTOCOL_VERSION};
+use protocol::entity::*;
+use protocol::block::Block;
+use protocol::coords::*;
+use crate::accounts::{self, Accounts, Login, PasswordResult, PasswordWorker, Role};
+use crate::backups::{self, Backups, Source};
+use crate::bans::BanList;
+use crate::chat::{self, Channel, ChatLog};
+use crate::client::Client;
+use crate::config::Config;
+use crate::edit::Clipboard;
+use crate::profiles::{Profile, Profiles};
+use crate::rate_limit::MessageKind;
+use crate::whitelist::Whitelist;
+use crate::commands::{self, CommandSender};
+use crate::transport::{Connection, Disconnected, Transport};
+use crate::world::World;
+use crate::log;
+
+/// The whole state of a running server.
+pub struct Game {
+	pub world: World,
+	pub entities: HashMap<u64, Entity>,
+	pub clients: Vec<Client>,
+	/// The number of the tick being processed.
+	pub tick: u64,
+	pub config: Config,
+	pub bans: BanList,
+	pub whitelist: Whitelist,
+	pub accounts: Accounts,
+	pub profiles: Profiles,
+	pub backups: Backups,
+	pub passwords: PasswordWorker,
+	/// Set to false by the `stop` command.
+	pub running: bool,
+	/// The blocks copied by each command sender.
+	pub clipboards: HashMap<CommandSender, Clipboard>,
+	/// The lowercase usernames of the muted players, with the end of their mute. Mutes are lost when the server stops.
+	pub mutes: HashMap<String, Instant>,
+	/// The time players must wait between two chat messages. Moderators are not limited.
+	pub slow_mode: Duration,
+	pub chat_log: ChatLog,
+	/// The top left corners of the chunks modified since the last tick.
+	modified_chunks: HashSet<(u64, u64)>,
+	last_save: Instant,
+	/// Commands sent by players during this tick, run after every client has been updated.
+	pending_commands: Vec<(CommandSender, String)>,
+	/// The results of the password jobs finished before this tick, by client id.
+	finished_logins: HashMap<u64, PasswordResult>,
+	/// Chat messages sent during this tick, as `(sender_id, receiver_id, message)`, routed after every client has been updated.
+	pending_chat: Vec<(u64, u64, String)>,
+}
+
+impl Default for Game {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Game {
+	pub fn new() -> Self {
+		Self::with_config(Config::default())
+	}
+
+	pub fn with_config(config: Config) -> Self {
+		Game {
+			world: World::new(),
+			entities: HashMap::new(),
+			clients: Vec::new(),
+			tick: 0,
+			config,
+			bans: BanList::default(),
+			whitelist: Whitelist::default(),
+			accounts: Accounts::default(),
+			profiles: Profiles::default(),
+			backups: Backups::default(),
+			passwords: PasswordWorker::default(),
+			running: true,
+			clipboards: HashMap::new(),
+			mutes: HashMap::new(),
+			slow_mode: Duration::from_secs(0),
+			chat_log: ChatLog::default(),
+			modified_chunks: HashSet::new(),
+			last_save: Instant::now(),
+			finished_logins: HashMap::new(),
+			pending_commands: Vec::new(),
+			pending_chat: Vec::new(),
+		}
+	}
+
+	/// Register a new connection and spawn its player.
+	pub fn add_connection(&mut self, mut connection: Box<dyn Connection>) {
+		let address = connection.peer_addr().map(|address| address.ip());
+		if let Some(ban) = address.and_then(|address| self.bans.find_address(address)) {
+			log(format!("connection refused from banned address {}", ban.target));
+			let _ = connection.send(&Message::Kick{reason: KickReason::Banned, message: ban.reason.clone()});
+			connection.close();
+			return;
+		}
+
+		let entity = Entity::spawn_player("undefined".to_string());
+		let loaded_chunks_top_left = self.config.view_top_left(&entity.coords);
+		let mut client = Client::new(entity.get_id(), connection, loaded_chunks_top_left, &self.config.rate_limits);
+		client.address = address;
+		self.clients.push(client);
+		self.entities.insert(entity.get_id(), entity);
+	}
+
+	/// Register every connection waiting on a transport.
+	pub fn accept_connections(&mut self, transport: &mut dyn Transport) {
+		while let Some(connection) = transport.accept() {
+			self.add_connection(connection);
+		}
+	}
+
+	/// Process the messages of every client and send them the updates they need.
+	pub fn tick(&mut self, tick: u64) {
+		self.tick = tick;
+		self.finished_logins.extend(self.passwords.poll());
+
+		let mut idx = 0;
+		while idx < self.clients.len() {
+			if self.update_client(idx).is_err() {
+				self.remove_client(idx);
+			} else {
+				idx += 1;
+			}
+		}
+
+		// the clients which have disconnected before the end of their login
+		self.finished_logins.clear();
+
+		for (sender, command) in std::mem::take(&mut self.pending_commands) {
+			self.execute_command(sender, &command);
+		}
+		for (sender_id, receiver_id, message) in std::mem::take(&mut self.pending_chat) {
+			self.route_chat_message(sender_id, receiver_id, message);
+		}
+
+		self.send_modified_chunks();
+		self.update_backups();
+
+		// the saved files must not change while a backup is being written
+		if !self.backups.is_running() {
+			if let Err(error) = self.save_lists() {
+				log(error);
+			}
+
+			let view_size = (self.config.view_width * 8, self.config.view_height * 8);
+			let views = self.clients.iter().map(|client| (client.loaded_chunks_top_left, view_size));
+			if let Err(error) = self.world.unload_unseen(views, self.config.unload_delay()) {
+				log(error);
+			}
+
+			if self.last_save.elapsed() >= self.config.autosave_interval() {
+				if let Err(error) = self.save() {
+					log(error);
+				}
+			}
+		}
+	}
+
+	/// Log the result of the finished backup and start a new one when it is time to.
+	fn update_backups(&mut self) {
+		if let Some(result) = self.backups.poll() {
+			match result {
+				Ok(name) => log(format!("Backup {} written", name)),
+				Err(error) => log(error),
+			}
+		}
+		if let Some(interval) = self.config.backup_interval() {
+			if !self.backups.is_running() && self.backups.last_backup.elapsed() >= interval {
+				if let Err(error) = self.start_backup() {
+					log(error);
+				}
+			}
+		}
+	}
+
+	/// The saved files, as they are named in the backups.
+	pub fn saved_files(&self) -> Vec<Source> {
+		vec![
+			("world", self.config.world_path.clone()),
+			("bans.yml", self.config.bans_path.clone()),
+			("whitelist.yml", self.config.whitelist_path.clone()),
+			("accounts.yml", self.config.accounts_path.clone()),
+			("players", self.config.players_path.clone()),
+		]
+	}
+
+	/// Save, then write a backup of the saved files in the background.
+	pub fn start_backup(&mut self) -> Result<(), String> {
+		self.save()?;
+		self.backups.start(self.config.backup_path.clone(), self.saved_files(), self.config.backups_kept)
+	}
+
+	/// Disconnect every client and replace the saved files by a backup, then load them again.
+	/// The changes made since the last save are lost.
+	pub fn restore(&mut self, name: &str) -> Result<(), String> {
+		if let Some(Err(error)) = self.backups.wait() {
+			log(error);
+		}
+		if !backups::list(&self.config.backup_path)?.iter().any(|backup| backup == name) {
+			return Err(format!("there is no backup named {}", name));
+		}
+
+		log(format!("Restoring the backup {}", name));
+		while !self.clients.is_empty() {
+			self.kick(0, KickReason::ServerStopping, "the world is being restored from a backup; you can reconnect in a few seconds");
+		}
+		backups::restore(&self.config.backup_path, name, &self.saved_files())?;
+		self.world = World::open(&self.config.world_path)?;
+		self.bans = BanList::load(&self.config.bans_path)?;
+		self.whitelist = Whitelist::load(&self.config.whitelist_path)?;
+		self.accounts = Accounts::load(&self.config.accounts_path)?;
+		self.profiles = Profiles::open(&self.config.players_path)?;
+		self.modified_chunks.clear();
+		self.last_save = Instant::now();
+		Ok(())
+	}
+
+	/// Change a block of the map. Clients will receive the modified chunk at the end of the tick.
+	/// Return false if the block was already there.
+	pub fn set_block(&mut self, (x, y): (u64, u64), block: Block) -> bool {
+		if !self.world.set_block((x, y), block) {
+			return false;
+		}
+		self.modified_chunks.insert((x - x % 8, y - y % 8));
+		true
+	}
+
+	/// Send the chunks modified since the last tick to the clients which have them loaded.
+	fn send_modified_chunks(&mut self) {
+		let (width, height) = (self.config.view_width, self.config.view_height);
+		for (x, y) in self.modified_chunks.drain() {
+			let chunk = self.world.get_chunk(x, y);
+			for client in self.clients.iter_mut() {
+				let (left, top) = client.loaded_chunks_top_left;
+				if x >= left && x < left + width * 8 && y >= top && y < top + height * 8 {
+					let _ = client.send(&Message::Chunk(chunk));
+				}
+			}
+		}
+	}
+
+	/// Send a message to every client.
+	pub fn broadcast(&mut self, message: &Message) {
+		for client in self.clients.iter_mut() {
+			let _ = client.send(message);
+		}
+	}
+
+	/// Save everything that must survive a restart.
+	pub fn save(&mut self) -> Result<(), String> {
+		if self.backups.is_running() {
+			return Err(String::from("can't save while a backup is being written; try again in a few seconds"));
+		}
+		self.last_save = Instant::now();
+		self.save_lists()?;
+		self.world.save()?;
+		self.profiles.flush()?;
+		for client in self.clients.iter_mut() {
+			if let (Some(profile), Some(entity)) = (&mut client.profile, self.entities.get(&client.id)) {
+				profile.update(entity);
+				self.profiles.save(profile)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Save the ban list, the whitelist and the accounts if they have changed.
+	/// Must not be called while a backup is being written.
+	fn save_lists(&mut self) -> Result<(), String> {
+		self.bans.flush().map_err(|error| format!("can't save the ban list: {}", error))?;
+		self.whitelist.flush()?;
+		self.accounts.flush()
+	}
+
+	/// Disconnect every client, save and make the main loop stop.
+	pub fn stop(&mut self, message: &str) -> Result<(), String> {
+		log("Stopping the server");
+		while !self.clients.is_empty() {
+			self.kick(0, KickReason::ServerStopping, message);
+		}
+		self.running = false;
+		if let Some(Err(error)) = self.backups.wait() {
+			log(error);
+		}
+		self.save()
+	}
+
+	/// Send a kick message to a client and remove it from the game.
+	pub fn kick(&mut self, idx: usize, reason: KickReason, message: impl Into<String>) -> Client {
+		self.clients[idx].kick(reason, message);
+		self.remove_client(idx)
+	}
+
+	/// Remove a client from the game, despawn its player and tell the other clients.
+	pub fn remove_client(&mut self, idx: usize) -> Client {
+		let mut client = self.clients.remove(idx);
+		client.connection.close();
+		let entity = self.entities.remove(&client.id);
+		let joined = client.profile.is_some();
+
+		if let (Some(mut profile), Some(entity)) = (client.profile.take(), entity) {
+			profile.update(&entity);
+			if self.backups.is_running() {
+				self.profiles.save_later(profile);
+			} else if let Err(error) = self.profiles.save(&profile) {
+				log(error);
+			}
+		}
+
+		for other in self.clients.iter_mut() {
+			if let Some(position) = other.loaded_entities.iter().position(|id| *id == client.id) {
+				other.loaded_entities.remove(position);
+				let _ = other.send(&Message::RemoveEntity{id: client.id});
+			}
+		}
+
+		log(format!("{} has disconnected", client.username));
+		// like their arrival, only the players who have joined see it
+		if joined {
+			self.route_chat_message(0, 0, format!("{} left the game", client.username));
+		}
+		client
+	}
+
+	/// Send a message from the server to a player.
+	pub fn system_message(&mut self, id: u64, message: impl Into<String>) {
+		if let Some(idx) = self.client_index(id) {
+			let receiver_name = self.clients[idx].username.clone();
+			let _ = self.clients[idx].send(&Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id: id, receiver_name, message: message.into()});
+		}
+	}
+
+	/// Check that a player can send a chat message now, and return it sanitized and filtered.
+	fn check_chat_message(&mut self, idx: usize, message: &str) -> Result<String, String> {
+		if self.clients[idx].profile.is_none() {
+			return Err(String::from("you must join the game before chatting"));
+		}
+		let message = chat::sanitize(message, self.config.max_chat_length)?;
+		let now = Instant::now();
+		let username = self.clients[idx].username.to_lowercase();
+		if let Some(until) = self.mutes.get(&username).copied() {
+			if until > now {
+				return Err(format!("you are muted for {}", chat::format_duration(until - now)));
+			}
+			self.mutes.remove(&username);
+		}
+
+		let role = self.role_of(CommandSender::Player(self.clients[idx].id));
+		let state = &mut self.clients[idx].chat;
+		if role < Role::Moderator {
+			if state.current == Channel::Staff {
+				let _ = state.leave(&Channel::Staff);
+				return Err(String::from("you are not allowed in the staff channel anymore"));
+			}
+			if let Some(last_message) = state.last_message {
+				let elapsed = now.duration_since(last_message);
+				if elapsed < self.slow_mode {
+					return Err(format!("slow mode is enabled; wait {} before sending another message", chat::format_duration(self.slow_mode - elapsed)));
+				}
+			}
+		}
+		state.last_message = Some(now);
+
+		Ok(chat::filter_words(&message, &self.config.word_filter))
+	}
+
+	/// Deliver a chat message to the players who can receive it.
+	/// Messages sent to 0 go to the current channel of the sender, others are whispers.
+	/// Messages from the server are received by everyone.
+	pub fn route_chat_message(&mut self, sender_id: u64, receiver_id: u64, message: String) {
+		let (sender_name, channel, message) = match self.client_index(sender_id) {
+			Some(idx) => match self.check_chat_message(idx, &message) {
+				Ok(message) => (self.clients[idx].username.clone(), self.clients[idx].chat.current.clone(), message),
+				Err(error) => return self.system_message(sender_id, error),
+			},
+			None if sender_id == 0 => (String::from("Server"), Channel::Global, message),
+			None => return,
+		};
+
+		if receiver_id != 0 {
+			let receiver = match self.client_index(receiver_id) {
+				Some(idx) if self.clients[idx].profile.is_some() => idx,
+				_ => return self.system_message(sender_id, format!("no connected player has the id {}", receiver_id)),
+			};
+			let line = format!("[{} -> {}] {}", sender_name, self.clients[receiver].username, message);
+			log(&line);
+			self.chat_log.write(&line);
+			let receiver_name = self.clients[receiver].username.clone();
+			let chat_message = Message::ChatMessage{sender_id, sender_name, receiver_id, receiver_name, message};
+			let _ = self.clients[receiver].send(&chat_message);
+			if sender_id != receiver_id {
+				if let Some(sender) = self.client_index(sender_id) {
+					let _ = self.clients[sender].send(&chat_message);
+				}
+			}
+			return;
+		}
+
+		let line = match channel {
+			Channel::Global => format!("[{}] {}", sender_name, message),
+			_ => format!("[{}] [{}] {}", channel, sender_name, message),
+		};
+		log(&line);
+		self.chat_log.write(&line);
+
+		let range = self.config.chat_range * 40.0;
+		let origin = self.entities.get(&sender_id).map(|entity| entity.coords.clone());
+		let recipients: Vec<usize> = (0..self.clients.len()).filter(|idx| {
+			let client = &self.clients[*idx];
+			if client.profile.is_none() {
+				return false;
+			}
+			if sender_id == 0 {
+				return true;
+			}
+			if !client.chat.joined.contains(&channel) {
+				return false;
+			}
+			match channel {
+				Channel::Local => match (&origin, self.entities.get(&client.id)) {
+					(Some(origin), Some(entity)) => origin.distance_from(&entity.coords) <= range,
+					_ => false,
+				},
+				Channel::Staff => self.role_of(CommandSender::Player(client.id)) >= Role::Moderator,
+				Channel::Global | Channel::Party(_) => true,
+			}
+		}).collect();
+
+		let message = match channel {
+			Channel::Global => message,
+			_ => format!("[{}] {}", channel, message),
+		};
+		// the server is not named, as in its other messages
+		let sender_name = if sender_id == 0 { String::new() } else { sender_name };
+		let chat_message = Message::ChatMessage{sender_id, sender_name, receiver_id, receiver_name: String::new(), message};
+		for idx in recipients {
+			let _ = self.clients[idx].send(&chat_message);
+		}
+	}
+
+	fn update_client(&mut self, idx: usize) -> Result<(), Disconnected> {
+		let tick = self.tick;
+		let Game { world, entities, clients, config, bans, whitelist, accounts, profiles, passwords, finished_logins, pending_commands, pending_chat, .. } = self;
+		let (width, height) = (config.view_width, config.view_height);
+		// only needed by the handshake, counting the player joining
+		let player_count = match clients[idx].profile {
+			None => clients.iter().filter(|client| client.profile.is_some()).count() as u32 + 1,
+			Some(_) => 0,
+		};
+		let client = &mut clients[idx];
+		let player = entities.get(&client.id).expect("entity should be existing");
+
+		let needed_chunks_top_left = config.view_top_left(&player.coords);
+		let mut finished_login = finished_logins.remove(&client.id);
+
+		for id in client.loaded_entities.clone() {
+			let entity = entities.get(&id).expect("entity does not exist");
+			client.send(&Message::TpEntity{id, coords: entity.coords.clone()})?;
+		}
+
+		// if we must load chunks to left
+		if needed_chunks_top_left.0 < client.loaded_chunks_top_left.0 {
+			for i in 0..(client.loaded_chunks_top_left.0 - needed_chunks_top_left.0) / 8 {
+				for j in 0..height {
+					client.send(&Message::Chunk(world.get_chunk(needed_chunks_top_left.0 + i * 8, client.loaded_chunks_top_left.1 + j * 8)))?;
+					client.send(&Message::UnloadChunk{x: needed_chunks_top_left.0 + i * 8 + width * 8, y: client.loaded_chunks_top_left.1 + j * 8})?;
+				}
+			}
+			client.loaded_chunks_top_left.0 = needed_chunks_top_left.0;
+		} else if needed_chunks_top_left.0 > client.loaded_chunks_top_left.0 {
+			for i in 0..(needed_chunks_top_left.0 - client.loaded_chunks_top_left.0) / 8 {
+				for j in 0..height {
+					client.send(&Message::Chunk(world.get_chunk(client.loaded_chunks_top_left.0 + i * 8 + width * 8, client.loaded_chunks_top_left.1 + j * 8)))?;
+					client.send(&Message::UnloadChunk{x: client.loaded_chunks_top_left.0 + i * 8, y: client.loaded_chunks_top_left.1 + j * 8})?;
+				}
+			}
+			client.loaded_chunks_top_left.0 = needed_chunks_top_left.0;
+		}
+
+		// if we must load chunks to top
+		if needed_chunks_top_left.1 < client.loaded_chunks_top_left.1 {
+			for i in 0..width {
+				for j in 0..(client.loaded_chunks_top_left.1 - needed_chunks_top_left.1) / 8 {
+					client.send(&Message::Chunk(world.get_chunk(client.loaded_chunks_top_left.0 + i * 8, needed_chunks_top_left.1 + j * 8)))?;
+					client.send(&Message::UnloadChunk{x: client.loaded_chunks_top_left.0 + i * 8, y: needed_chunks_top_left.1 + j * 8 + height * 8})?;
+				}
+			}
+			client.loaded_chunks_top_left.1 = needed_chunks_top_left.1;
+		} else if needed_chunks_top_left.1 > client.loaded_chunks_top_left.1 {
+			for i in 0..width {
+				for j in 0..(needed_chunks_top_left.1 - client.loaded_chunks_top_left.1) / 8 {
+					client.send(&Message::Chunk(world.get_chunk(client.loaded_chunks_top_left.0 + i * 8, client.loaded_chunks_top_left.1 + height * 8 + j * 8)))?;
+					client.send(&Message::UnloadChunk{x: client.loaded_chunks_top_left.0 + i * 8, y: client.loaded_chunks_top_left.1 + j * 8})?;
+				}
+			}
+			client.loaded_chunks_top_left.1 = needed_chunks_top_left.1;
+		}
+
+		// the remaining messages wait for the next ticks, so that a client can't keep the loop busy
+		for _ in 0..config.max_messages_per_tick {
+			let now = Instant::now();
+			let message = match client.connection.receive()? {
+				Some(Ok(message)) => message,
+				Some(Err(error)) => {
+					client.last_message = now;
+					log(format!("{} sent an invalid frame: {}", client.username, error));
+					client.warn_flooding(config, "too many invalid messages")?;
+					continue;
+				},
+				None => break,
+			};
+			client.last_message = now;
+			let player = entities.get_mut(&client.id).expect("entity should be existing");
+			let kind = MessageKind::of(&message);
+			if !client.rate_limiter.allow(kind, now) {
+				client.warn_flooding(config, format!("too many {} messages", kind))?;
+				if kind == MessageKind::Movement {
+					// the dropped move would make the next ones look invalid
+					client.send(&Message::TpEntity{id: player.get_id(), coords: player.coords.clone()})?;
+				}
+				continue;
+			}
+			match message {
+				Message::InitServer{username, screen_width: _, screen_height: _, password, protocol_version} if client.profile.is_none() && client.pending_login.is_none() => {
+					if protocol_version != PROTOCOL_VERSION {
+						client.kick(KickReason::VersionMismatch, format!("the server uses the protocol version {} but your client uses the version {}", PROTOCOL_VERSION, protocol_version));
+						return Err(Disconnected);
+					}
+					// the username is not logged before being validated since it could contain anything
+					if let Err(error) = accounts::validate_username(&username, config) {
+						client.kick(KickReason::InvalidUsername, error);
+						return Err(Disconnected);
+					}
+					client.username = username;
+					if let Some(ban) = bans.find_name(&client.username) {
+						client.kick(KickReason::Banned, ban.reason.clone());
+						return Err(Disconnected);
+					}
+					let (login, job) = match accounts.login(&client.username, password.as_deref(), config.allow_registration, config.require_password) {
+						Ok(login) => login,
+						Err((reason, message)) => {
+							client.kick(reason, message);
+							return Err(Disconnected);
+						},
+					};
+					if whitelist.enabled && !whitelist.contains(&client.username) && accounts.role(&client.username) < Role::Moderator {
+						client.kick(KickReason::NotWhitelisted, "you are not whitelisted on this server");
+						return Err(Disconnected);
+					}
+					if is_playing(entities, &client.username) {
+						client.kick(KickReason::AlreadyConnected, "someone is already playing with this account");
+						return Err(Disconnected);
+					}
+					client.pending_login = Some(login);
+					match job {
+						// the password is checked in the background, the player joins once it is done
+						Some(job) => passwords.start(client.id, job),
+						None => {
+							finished_login = Some(Ok(None));
+							// the next messages need the player to have joined
+							break;
+						},
+					}
+				},
+				Message::TpEntity{id, coords} => {
+					if id == client.id {
+						// the distance is checked first, so that forged coordinates never make the server read far away regions
+						if player.coords.distance_from(&coords) <= player.get_speed().into() && can_stand(world, &coords) {
+							if let Some(profile) = &mut client.profile {
+								profile.stats.distance_walked += player.coords.distance_from(&coords) / 40.0;
+							}
+							player.coords = coords;
+						} else {
+							client.warn_cheating(config, "too many invalid moves")?;
+
+							client.send(&Message::TpEntity{id: player.get_id(), coords: player.coords.clone()})?;
+						}
+					} else {
+						client.warn_cheating(config, "attempt to move an unowned entity")?;
+					}
+				},
+				Message::ChatMessage{message, ..} if message.starts_with('/') && client.profile.is_some() => {
+					pending_commands.push((CommandSender::Player(client.id), message[1..].to_string()));
+				},
+				// the sender can't be chosen by the client
+				Message::ChatMessage{receiver_id, message, ..} if client.profile.is_some() => {
+					pending_chat.push((client.id, receiver_id, message));
+				},
+				Message::Ping(id) => {
+					client.send(&Message::Pong(id))?;
+				},
+				Message::Pong(id) => {
+					if let Some((expected_id, sent)) = client.pending_ping {
+						if id == expected_id {
+							client.latency = Some(sent.elapsed());
+							client.pending_ping = None;
+						}
+					}
+				},
+				// the password of a second login must not end up in the logs
+				Message::InitServer{..} => {
+					client.warn_cheating(config, "duplicate login")?;
+				},
+				// never printed, since clients could write anything in the console
+				_ => {
+					client.warn_flooding(config, format!("too many unexpected {} messages", kind))?;
+				},
+			}
+		}
+
+		if let Some(result) = finished_login {
+			let login = client.pending_login.take().expect("a login should be pending");
+			let password_hash = match result {
+				Ok(password_hash) => password_hash,
+				Err((reason, message)) => {
+					client.kick(reason, message);
+					return Err(Disconnected);
+				},
+			};
+			// someone may have joined with the same username while the password was checked
+			let username = client.username.clone();
+			if is_playing(entities, &username) {
+				client.kick(KickReason::AlreadyConnected, "someone is already playing with this account");
+				return Err(Disconnected);
+			}
+			if login == Login::Registered && accounts.find(&username).is_some() {
+				client.kick(KickReason::WrongPassword, "someone has just registered this username");
+				return Err(Disconnected);
+			}
+			let mut profile = match profiles.load(&username) {
+				Ok(Some(profile)) => profile,
+				Ok(None) => Profile::new(&username, client.id),
+				Err(error) => {
+					log(error);
+					client.kick(KickReason::Kicked, "your profile can't be loaded; please contact an operator");
+					return Err(Disconnected);
+				},
+			};
+			// the account is only created once nothing can prevent the player from joining
+			if let Some(password_hash) = password_hash {
+				accounts.set_password_hash(&username, password_hash);
+			}
+			match login {
+				Login::Authenticated => log(format!("{} has connected", username)),
+				Login::Registered => log(format!("{} has connected with a new account", username)),
+				Login::Guest => log(format!("{} has connected without account", username)),
+			}
+
+			// the player gets back its id and its position
+			profile.username = username.clone();
+			profile.login();
+			let player = profile.spawn();
+			entities.remove(&client.id);
+			entities.insert(player.get_id(), player.clone());
+			client.id = player.get_id();
+			pending_chat.push((0, 0, format!("{} joined the game", username)));
+			client.profile = Some(profile);
+			client.loaded_chunks_top_left = config.view_top_left(&player.coords);
+			client.send(&Message::CreateEntity(player.clone()))?;
+			client.send(&Message::InitClient{id: player.get_id()})?;
+			client.send(&Message::ServerInfo{
+				name: config.server_name.clone(),
+				motd: config.motd.clone(),
+				protocol_version: PROTOCOL_VERSION,
+				tick_rate: config.tick_rate,
+				player_count,
+				view_width: width,
+				view_height: height,
+			})?;
+
+			for i in 0..width {
+				for j in 0..height {
+					client.send(&Message::Chunk(world.get_chunk(client.loaded_chunks_top_left.0 + i * 8, client.loaded_chunks_top_left.1 + j * 8)))?;
+				}
+			}
+		}
+
+		let now = Instant::now();
+		if now - client.last_message > config.timeout() {
+			client.kick(KickReason::Timeout, format!("no message received for {}s", config.timeout));
+			return Err(Disconnected);
+		}
+		if client.connection.queued_bytes() > config.slow_client_bytes {
+			let slow_since = *client.slow_since.get_or_insert(now);
+			if now - slow_since > config.slow_client_timeout() {
+				client.kick(KickReason::Timeout, "your connection is too slow");
+				return Err(Disconnected);
+			}
+		} else {
+			client.slow_since = None;
+		}
+		if client.pending_ping.is_none() && now - client.last_ping >= config.heartbeat_interval() {
+			client.send(&Message::Ping(tick))?;
+			client.pending_ping = Some((tick, now));
+			client.last_ping = now;
+		}
+
+		client.send(&Message::Tick(tick))
+	}
+
+	/// Return the index of the client of a player.
+	pub fn client_index(&self, id: u64) -> Option<usize> {
+		self.clients.iter().position(|client| client.id == id)
+	}
+
+	/// Return the index of the client of a player designated by its username or its id.
+	pub fn find_player(&self, name: &str) -> Option<usize> {
+		self.clients.iter().position(|client| client.username.eq_ignore_ascii_case(name))
+			.or_else(|| name.parse().ok().and_then(|id| self.client_index(id)))
+	}
+
+	pub fn role_of(&self, sender: CommandSender) -> Role {
+		match sender {
+			CommandSender::Console => Role::Operator,
+			CommandSender::Player(id) => match self.client_index(id) {
+				// the username is only checked during the handshake
+				Some(idx) if self.clients[idx].profile.is_some() => self.accounts.role(&self.clients[idx].username),
+				_ => Role::Player,
+			},
+		}
+	}
+
+	/// Run a command and send the result back to the sender.
+	pub fn execute_command(&mut self, sender: CommandSender, line: &str) {
+		let output = match commands::execute(self, sender, line) {
+			Ok(output) => output,
+			Err(error) => error,
+		};
+
+		match sender {
+			CommandSender::Console => println!("{}", output),
+			CommandSender::Player(id) => {
+				for line in output.lines() {
+					self.system_message(id, line);
+				}
+			},
+		}
+	}
+}
+
+/// Whether the four blocks covered by a player at these coordinates are free.
+fn can_stand(world: &mut World, coords: &Coords) -> bool {
+	let right = Coords::new(SingleAxis::new(1, 0), SingleAxis::new(0, 0));
+	let up = Coords::new(SingleAxis::new(0, 0), SingleAxis::new(1, 0));
+	[coords.clone(), coords.clone() + right.clone(), coords.clone() - up.clone(), coords.clone() + right - up]
+		.iter()
+		.all(|position| !world.block(position.clone().into()).is_solid())
+}
+
+/// Whether a player with this username, whatever its case, has joined.
+fn is_playing(entities: &HashMap<u64, Entity>, username: &str) -> bool {
+	entities.values().any(|entity| *entity.get_type() == EntityType::Player && entity.get_name().eq_ignore_ascii_case(username))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+	use protocol::block::{BlockCode, Orientation};
+	use crate::transport::{MemoryClient, MemoryConnector, MemoryTransport};
+
+	/// The handshake of a client.
+	fn init(username: &str, password: Option<&str>) -> Message {
+		Message::InitServer{username: username.to_string(), screen_width: 800, screen_height: 600, password: password.map(String::from), protocol_version: PROTOCOL_VERSION}
+	}
+
+	/// Connect a client and send its handshake.
+	fn join(connector: &MemoryConnector, username: &str, password: Option<&str>) -> MemoryClient {
+		let client = connector.connect().unwrap();
+		client.send(&init(username, password)).unwrap();
+		client
+	}
+
+	/// Run a tick, then run it again once the passwords received have been checked, so that their players have joined.
+	fn tick_with_logins(game: &mut Game, tick: u64) {
+		game.tick(tick);
+		game.passwords.wait();
+		game.tick(tick);
+	}
+
+	/// A chat message to everyone, as sent by a client.
+	fn chat(message: &str) -> Message {
+		Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id: 0, receiver_name: String::new(), message: message.to_string()}
+	}
+
+	fn whisper(receiver_id: u64, message: &str) -> Message {
+		Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id, receiver_name: String::new(), message: message.to_string()}
+	}
+
+	/// The chat messages among `messages`, as `(sender_id, receiver_id, message)`.
+	fn chats(messages: Vec<Message>) -> Vec<(u64, u64, String)> {
+		messages.into_iter().filter_map(|message| match message {
+			Message::ChatMessage{sender_id, receiver_id, message, ..} => Some((sender_id, receiver_id, message)),
+			_ => None,
+		}).collect()
+	}
+
+	/// The text of the chat messages among `messages`.
+	fn texts(messages: Vec<Message>) -> Vec<String> {
+		chats(messages).into_iter().map(|(_, _, message)| message).collect()
+	}
+
+	#[test]
+	fn test_memory_handshake() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let client = connector.connect().unwrap();
+
+		client.send(&init("tester", None)).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(42);
+
+		assert_eq!(game.clients.len(), 1);
+		assert_eq!(game.clients[0].username, "tester");
+
+		let messages = client.drain();
+		let init = messages.iter().position(|message| *message == Message::InitClient{id: game.clients[0].id}).unwrap();
+		assert_eq!(messages[init + 1], Message::ServerInfo{
+			name: game.config.server_name.clone(),
+			motd: game.config.motd.clone(),
+			protocol_version: PROTOCOL_VERSION,
+			tick_rate: 60,
+			player_count: 1,
+			view_width: 8,
+			view_height: 4,
+		});
+		assert_eq!(messages.iter().filter(|message| matches!(message, Message::Chunk(_))).count(), 32);
+		assert_eq!(messages.iter().rev().find(|message| !matches!(message, Message::ChatMessage{..})), Some(&Message::Tick(42)));
+		assert_eq!(chats(messages).pop(), Some((0, 0, String::from("tester joined the game"))));
+	}
+
+	#[test]
+	fn test_version_mismatch() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let client = connector.connect().unwrap();
+		// sent by a client older than the protocol versions
+		client.send_raw(String::from("InitServer:\n  username: tester\n  screen_width: 800\n  screen_height: 600\n  password: ~\n")).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		assert!(game.clients.is_empty());
+		assert!(client.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::VersionMismatch, ..})));
+	}
+
+	#[test]
+	fn test_unexpected_messages() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let client = connector.connect().unwrap();
+		client.send(&chat("\x1B[2Jbefore joining")).unwrap();
+		client.send(&init("tester", None)).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		assert_eq!(game.clients[0].flooding_warns.count(), 1);
+
+		client.send(&init("tester", Some("secret"))).unwrap();
+		game.tick(1);
+		assert_eq!(game.clients[0].cheating_warns.count(), 1);
+		assert_eq!(game.clients[0].username, "tester");
+	}
+
+	#[test]
+	fn test_disconnect() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let observer = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+
+		for tick in 0..20 {
+			let mut client = join(&connector, &format!("tester{}", tick), None);
+			game.accept_connections(&mut transport);
+			game.tick(tick * 2);
+			assert_eq!(game.clients.len(), 2);
+			assert_eq!(game.entities.len(), 2);
+
+			let id = game.clients[1].id;
+			game.clients[0].loaded_entities.push(id);
+			observer.drain();
+
+			client.close();
+			game.tick(tick * 2 + 1);
+			assert_eq!(game.clients.len(), 1);
+			assert_eq!(game.entities.len(), 1);
+			assert!(game.clients[0].loaded_entities.is_empty());
+			assert!(observer.drain().contains(&Message::RemoveEntity{id}));
+		}
+	}
+
+	#[test]
+	fn test_heartbeat() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.config.heartbeat_interval = 0.0;
+		let client = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+
+		game.tick(1);
+		assert!(client.drain().contains(&Message::Ping(1)));
+		assert!(game.clients[0].pending_ping.is_some());
+
+		client.send(&Message::Pong(1)).unwrap();
+		game.tick(2);
+		assert!(game.clients[0].latency.is_some());
+		assert!(client.drain().contains(&Message::Ping(2)));
+	}
+
+	#[test]
+	fn test_timeout() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.config.timeout = 0.05;
+		let client = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+
+		game.tick(1);
+		assert_eq!(game.clients.len(), 1);
+
+		std::thread::sleep(Duration::from_millis(60));
+		client.send(&Message::Pong(1)).unwrap();
+		game.tick(2);
+		assert_eq!(game.clients.len(), 1);
+
+		std::thread::sleep(Duration::from_millis(60));
+		game.tick(3);
+		assert!(game.clients.is_empty());
+		assert!(game.entities.is_empty());
+		assert!(client.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::Timeout, ..})));
+	}
+
+	#[test]
+	fn test_far_move() {
+		let path = std::env::temp_dir().join(format!("dungeon_far_move_{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.world = World::open(&path).unwrap();
+		let client = join(&connector, "player", None);
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 0);
+		let id = game.clients[0].id;
+
+		// a forged move is rejected without reading the region it points to
+		let far_away = Coords::default() + Coords::new(SingleAxis::new(1_000_000, 0), SingleAxis::new(0, 0));
+		let (x, y): (u64, u64) = far_away.clone().into();
+		let region_path = World::region_path(&path, (x / crate::world::REGION_SIZE, y / crate::world::REGION_SIZE));
+		std::fs::write(&region_path, b"not a region").unwrap();
+		client.drain();
+		client.send(&Message::TpEntity{id, coords: far_away}).unwrap();
+		tick_with_logins(&mut game, 1);
+
+		assert!(region_path.exists());
+		assert_eq!(game.clients[0].cheating_warns.count(), 1);
+		assert!(client.drain().contains(&Message::TpEntity{id, coords: Coords::default()}));
+
+		// nor can a player move another entity
+		client.send(&Message::TpEntity{id: id + 1000, coords: Coords::default()}).unwrap();
+		tick_with_logins(&mut game, 2);
+		assert_eq!(game.clients[0].cheating_warns.count(), 2);
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn test_kick_cheater() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let client = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		let id = game.clients[0].id;
+
+		let far_away = Coords::default() + Coords::new(SingleAxis::new(100, 0), SingleAxis::new(0, 0));
+		for tick in 1..=(game.config.max_warns as u64 + 1) {
+			client.send(&Message::TpEntity{id, coords: far_away.clone()}).unwrap();
+			game.tick(tick);
+		}
+
+		assert!(game.clients.is_empty());
+		assert!(game.entities.is_empty());
+		let messages = client.drain();
+		assert!(messages.iter().any(|message| matches!(message, Message::Kick{reason: KickReason::Cheating, ..})));
+		assert_eq!(client.try_receive(), Err(crate::transport::Disconnected));
+	}
+
+	#[test]
+	fn test_flooding() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let is_flooding = |messages: Vec<Message>| messages.iter().any(|message| matches!(message, Message::Kick{reason: KickReason::Flooding, ..}));
+		let spammer = join(&connector, "spammer", None);
+		let player = join(&connector, "player", None);
+		game.accept_connections(&mut transport);
+		game.tick(0);
+
+		// a burst is allowed, then every message over the limit is a warn
+		let help = chat("/help");
+		for _ in 0..game.config.rate_limits.chat.burst as u16 + game.config.max_warns {
+			spammer.send(&help).unwrap();
+		}
+		player.send(&help).unwrap();
+		game.tick(1);
+		assert_eq!(game.clients.len(), 2);
+		assert_eq!(game.clients[0].flooding_warns.count(), game.config.max_warns);
+
+		// rate limited messages are not mistaken for cheating
+		let id = game.clients[0].id;
+		let far_away = Coords::default() + Coords::new(SingleAxis::new(100, 0), SingleAxis::new(0, 0));
+		spammer.send(&Message::TpEntity{id, coords: far_away}).unwrap();
+		game.tick(2);
+		assert_eq!(game.clients.len(), 2);
+		assert_eq!(game.clients[0].cheating_warns.count(), 1);
+		spammer.send(&help).unwrap();
+		game.tick(3);
+		assert_eq!(game.clients.len(), 1);
+		assert!(is_flooding(spammer.drain()));
+
+		// a client can't make the server process all its messages at once
+		game.config.max_messages_per_tick = 3;
+		for _ in 0..5 {
+			player.send(&Message::Pong(0)).unwrap();
+		}
+		game.tick(4);
+		assert_eq!(game.clients.len(), 1);
+		assert!(game.clients[0].connection.receive().unwrap().is_some());
+		game.config.max_messages_per_tick = Config::default().max_messages_per_tick;
+
+		let garbage = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		for _ in 0..=game.config.max_warns {
+			garbage.send_raw(String::from("not a message")).unwrap();
+		}
+		game.tick(5);
+		assert!(is_flooding(garbage.drain()));
+
+		transport.max_message_size = 100;
+		let large = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		let message = chat(&"a".repeat(200));
+		for _ in 0..=game.config.max_warns {
+			large.send(&message).unwrap();
+		}
+		game.tick(6);
+		assert!(is_flooding(large.drain()));
+		assert_eq!(game.clients.len(), 1);
+	}
+
+	#[test]
+	fn test_slow_clients() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.config.slow_client_bytes = 1000;
+		game.config.slow_client_timeout = 60.0;
+		let reader = join(&connector, "reader", None);
+		let stalled = join(&connector, "stalled", None);
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		reader.drain();
+		assert!(game.clients[0].connection.bytes_sent() > 0);
+		assert_eq!(game.clients[0].connection.queued_bytes(), 0);
+
+		// the chunks sent on connection are enough to be slow
+		assert!(game.clients[1].connection.queued_bytes() > 1000);
+		game.tick(1);
+		assert!(game.clients[1].slow_since.is_some());
+		game.config.slow_client_timeout = 0.0;
+		reader.drain();
+		game.tick(2);
+		assert_eq!(game.clients.len(), 1);
+		assert!(stalled.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::Timeout, ..})));
+		assert!(game.clients[0].slow_since.is_none());
+
+		// the queue can't grow beyond its bound
+		transport.max_queued_bytes = 1000;
+		let _stalled = join(&connector, "stalled", None);
+		game.accept_connections(&mut transport);
+		game.tick(3);
+		assert_eq!(game.clients.len(), 1);
+	}
+
+	#[test]
+	fn test_chat() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let alice = join(&connector, "Alice", None);
+		let bob = join(&connector, "Bob", None);
+		let carol = join(&connector, "Carol", None);
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		assert_eq!(chats(alice.drain()).last().unwrap(), &(0, 0, String::from("Carol joined the game")));
+		bob.drain();
+		carol.drain();
+		let (alice_id, bob_id) = (game.clients[0].id, game.clients[1].id);
+
+		// the sender can't be spoofed and the message is cleaned
+		alice.send(&Message::ChatMessage{sender_id: bob_id, sender_name: String::from("Bob"), receiver_id: 0, receiver_name: String::new(), message: String::from(" hello\x1B ")}).unwrap();
+		game.tick(1);
+		for client in [&alice, &bob, &carol].iter() {
+			assert_eq!(chats(client.drain()), vec![(alice_id, 0, String::from("hello"))]);
+		}
+
+		alice.send(&whisper(bob_id, "psst")).unwrap();
+		alice.send(&whisper(42, "anyone?")).unwrap();
+		alice.send(&chat(&"a".repeat(game.config.max_chat_length + 1))).unwrap();
+		game.tick(2);
+		assert_eq!(chats(alice.drain()), vec![
+			(alice_id, bob_id, String::from("psst")),
+			(0, alice_id, String::from("no connected player has the id 42")),
+			(0, alice_id, format!("messages can't be longer than {} characters", game.config.max_chat_length)),
+		]);
+		assert_eq!(chats(bob.drain()), vec![(alice_id, bob_id, String::from("psst"))]);
+		assert!(chats(carol.drain()).is_empty());
+
+		// the local channel only reaches the players within the chat range
+		game.config.chat_range = 10.0;
+		game.entities.get_mut(&game.clients[2].id).unwrap().coords.x.main += 11 * 40;
+		bob.send(&chat("/join local")).unwrap();
+		bob.send(&chat("who is there?")).unwrap();
+		game.tick(3);
+		assert_eq!(chats(alice.drain()), vec![(bob_id, 0, String::from("[local] who is there?"))]);
+		assert!(chats(carol.drain()).is_empty());
+
+		// clients which have not joined neither see the departures nor are announced
+		let connecting = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		drop(bob);
+		game.kick(1, KickReason::Kicked, "bye");
+		assert_eq!(chats(carol.drain()), vec![(0, 0, String::from("Bob left the game"))]);
+		assert!(chats(connecting.drain()).is_empty());
+		drop(connecting);
+		game.kick(2, KickReason::Kicked, "bye");
+		assert!(chats(carol.drain()).is_empty());
+	}
+
+	#[test]
+	fn test_chat_moderation() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.config.word_filter = vec![String::from("heck")];
+		game.config.rate_limits.chat = crate::rate_limit::Rate::new(100.0, 100.0);
+		game.accounts.set_password("boss", "secret").unwrap();
+		game.accounts.set_role("boss", Role::Moderator);
+		let alice = join(&connector, "Alice", None);
+		let bob = join(&connector, "Bob", None);
+		let boss = join(&connector, "boss", Some("secret"));
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 0);
+		for client in [&alice, &bob, &boss].iter() {
+			client.drain();
+		}
+
+		// parties and the staff channel only reach their members
+		alice.send(&chat("/join party Red")).unwrap();
+		bob.send(&chat("/join staff")).unwrap();
+		boss.send(&chat("/join staff")).unwrap();
+		game.tick(1);
+		assert_eq!(texts(alice.drain()), vec![String::from("your messages are now sent to the party red channel")]);
+		assert_eq!(texts(bob.drain()), vec![String::from("only moderators can join the staff channel")]);
+		boss.drain();
+
+		alice.send(&chat("what the heck, checking in")).unwrap();
+		boss.send(&chat("staff only")).unwrap();
+		game.tick(2);
+		assert_eq!(texts(alice.drain()), vec![String::from("[party red] what the ****, checking in")]);
+		assert!(texts(bob.drain()).is_empty());
+		assert_eq!(texts(boss.drain()), vec![String::from("[staff] staff only")]);
+
+		alice.send(&chat("/leave party")).unwrap();
+		alice.send(&chat("/channels")).unwrap();
+		game.tick(3);
+		assert_eq!(texts(alice.drain()), vec![
+			String::from("you left the party red channel; your messages are sent to the global channel"),
+			String::from("you are in: global, local; your messages are sent to the global channel"),
+		]);
+
+		// mutes have a duration and moderators can't be muted by moderators
+		boss.send(&chat("/mute alice 10m spam")).unwrap();
+		boss.send(&chat("/mute boss 10m")).unwrap();
+		game.tick(4);
+		assert_eq!(texts(boss.drain()), vec![
+			String::from("alice has been muted for 10m"),
+			String::from("you can't do that to boss, whose role is moderator"),
+		]);
+		assert_eq!(texts(alice.drain()), vec![String::from("you have been muted for 10m: spam")]);
+		alice.send(&chat("hello?")).unwrap();
+		game.tick(5);
+		assert!(texts(alice.drain())[0].starts_with("you are muted for "));
+		assert!(texts(bob.drain()).is_empty());
+		game.execute_command(CommandSender::Console, "unmute Alice");
+		alice.send(&chat("hello")).unwrap();
+		game.tick(6);
+		assert_eq!(texts(bob.drain()), vec![String::from("hello")]);
+
+		// the slow mode does not limit moderators
+		game.execute_command(CommandSender::Console, "slowmode 1h");
+		game.clients[0].chat.last_message = None;
+		alice.drain();
+		alice.send(&chat("one")).unwrap();
+		boss.send(&chat("/join global")).unwrap();
+		boss.send(&chat("a")).unwrap();
+		boss.send(&chat("b")).unwrap();
+		game.tick(7);
+		alice.send(&chat("two")).unwrap();
+		game.tick(8);
+		assert_eq!(texts(bob.drain()), vec![String::from("one"), String::from("a"), String::from("b")]);
+		assert_eq!(texts(alice.drain()).last().unwrap(), "slow mode is enabled; wait 1h before sending another message");
+		game.execute_command(CommandSender::Console, "slowmode 0");
+		assert_eq!(game.slow_mode, Duration::from_secs(0));
+	}
+
+	#[test]
+	fn test_player_commands() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let alice = join(&connector, "Alice", None);
+		let bob = join(&connector, "Bob", None);
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		alice.drain();
+		bob.drain();
+		let (alice_id, bob_id) = (game.clients[0].id, game.clients[1].id);
+
+		alice.send(&chat("/who")).unwrap();
+		alice.send(&chat("/ping")).unwrap();
+		alice.send(&chat("/msg bob meet me at the spawn")).unwrap();
+		game.tick(1);
+		let system = |message: &str| Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id: alice_id, receiver_name: String::from("Alice"), message: message.to_string()};
+		// the names are sent since the players don't know each other's entities
+		let whisper = || Message::ChatMessage{sender_id: alice_id, sender_name: String::from("Alice"), receiver_id: bob_id, receiver_name: String::from("Bob"), message: String::from("meet me at the spawn")};
+		assert_eq!(alice.drain(), vec![
+			Message::Tick(1),
+			system("2 players online: Alice, Bob"),
+			system("your ping has not been measured yet"),
+			whisper(),
+		]);
+		assert_eq!(bob.drain(), vec![Message::Tick(1), whisper()]);
+
+		bob.send(&chat("hi Alice")).unwrap();
+		game.tick(2);
+		let hello = Message::ChatMessage{sender_id: bob_id, sender_name: String::from("Bob"), receiver_id: 0, receiver_name: String::new(), message: String::from("hi Alice")};
+		assert!(alice.drain().contains(&hello));
+		bob.drain();
+
+		// players need to finish the handshake before running commands
+		let stranger = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		stranger.send(&chat("/who")).unwrap();
+		stranger.send(&chat("/msg bob hello")).unwrap();
+		game.tick(3);
+		assert!(chats(stranger.drain()).is_empty());
+		assert!(chats(bob.drain()).is_empty());
+
+		// whispering to yourself gives a single copy
+		alice.send(&chat("/msg alice note to self")).unwrap();
+		game.tick(4);
+		assert_eq!(chats(alice.drain()), vec![(alice_id, alice_id, String::from("note to self"))]);
+
+		// the player is told where it has been moved
+		game.entities.get_mut(&bob_id).unwrap().coords.x.main += 5;
+		bob.send(&chat("/spawn")).unwrap();
+		game.tick(5);
+		assert_eq!(game.entities[&bob_id].coords, Coords::default());
+		let messages = bob.drain();
+		assert!(messages.contains(&Message::TpEntity{id: bob_id, coords: Coords::default()}));
+		assert!(chats(messages).contains(&(0, bob_id, String::from("you have been teleported to the spawn"))));
+	}
+
+	#[test]
+	fn test_chat_commands() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.accounts.set_password("boss", "secret").unwrap();
+		game.execute_command(CommandSender::Console, "op boss moderator");
+		assert_eq!(game.accounts.role("boss"), Role::Moderator);
+		let player = join(&connector, "player", None);
+		let admin = join(&connector, "boss", Some("secret"));
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 0);
+		let _connecting = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		player.drain();
+		admin.drain();
+
+		player.send(&chat("/list")).unwrap();
+		admin.send(&chat("/list")).unwrap();
+		admin.send(&chat("/setblock 0 0 simple_wall")).unwrap();
+		game.tick(1);
+
+		assert_eq!(texts(player.drain()), vec![String::from("you don't have the permission to use list")]);
+		let admin_replies = texts(admin.drain());
+		assert_eq!(admin_replies[0], "2 players connected");
+		assert!(admin_replies[3].starts_with("connecting (id: "));
+		assert_eq!(admin_replies.last().unwrap(), "you don't have the permission to use setblock");
+
+		// moderators can't act on other moderators, and roles take effect immediately
+		game.accounts.set_password("player", "password").unwrap();
+		game.execute_command(CommandSender::Console, "op player operator");
+		assert_eq!(texts(player.drain()), vec![String::from("your role is now operator")]);
+		admin.send(&chat("/kick player")).unwrap();
+		game.tick(2);
+		assert_eq!(texts(admin.drain()), vec![String::from("you can't do that to player, whose role is operator")]);
+		player.send(&chat("/deop boss")).unwrap();
+		game.tick(3);
+		assert_eq!(texts(admin.drain()), vec![String::from("your role is now player")]);
+		assert_eq!(texts(player.drain()), vec![String::from("boss now has the role player")]);
+		assert_eq!(game.role_of(CommandSender::Player(game.clients[1].id)), Role::Player);
+	}
+
+	#[test]
+	fn test_admin_commands() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let address: std::net::SocketAddr = "10.0.0.1:4000".parse().unwrap();
+		let alice = connector.connect().unwrap();
+		let bob = connector.connect_from(address).unwrap();
+		alice.send(&init("Alice", None)).unwrap();
+		bob.send(&init("Bob", None)).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		alice.drain();
+
+		game.execute_command(CommandSender::Console, "say hello everyone");
+		assert!(chats(alice.drain()).contains(&(0, 0, String::from("hello everyone"))));
+
+		// ban by name, whatever the case, then by address
+		game.execute_command(CommandSender::Console, "ban alice griefing");
+		assert_eq!(game.clients.len(), 1);
+		assert!(alice.drain().contains(&Message::Kick{reason: KickReason::Banned, message: String::from("griefing")}));
+		let _alice = join(&connector, "ALICE", None);
+		game.accept_connections(&mut transport);
+		game.tick(1);
+		assert_eq!(game.clients.len(), 1);
+
+		game.execute_command(CommandSender::Console, "ban_ip 10.0.0.1");
+		assert!(game.clients.is_empty());
+		let _bob = connector.connect_from(address).unwrap();
+		game.accept_connections(&mut transport);
+		assert!(game.clients.is_empty());
+
+		game.execute_command(CommandSender::Console, "unban_ip 10.0.0.1");
+		game.execute_command(CommandSender::Console, "unban Alice");
+		let _bob = connector.connect_from(address).unwrap();
+		game.accept_connections(&mut transport);
+		assert_eq!(game.clients.len(), 1);
+		assert!(game.bans.names.is_empty() && game.bans.addresses.is_empty());
+
+		game.execute_command(CommandSender::Console, "stop");
+		assert!(game.clients.is_empty());
+		assert!(!game.running);
+	}
+
+	#[test]
+	fn test_whitelist() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let alice = join(&connector, "Alice", None);
+		let bob = join(&connector, "Bob", None);
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		assert_eq!(game.clients.len(), 2);
+
+		game.execute_command(CommandSender::Console, "whitelist_add alice");
+		game.execute_command(CommandSender::Console, "whitelist on");
+		assert_eq!(game.clients.len(), 1);
+		assert!(bob.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::NotWhitelisted, ..})));
+		assert!(game.whitelist.enabled);
+
+		let bob = join(&connector, "bob", None);
+		let _alice = join(&connector, "ALICE", None);
+		game.accept_connections(&mut transport);
+		game.tick(1);
+		assert!(bob.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::NotWhitelisted, ..})));
+		// Alice is already connected
+		assert_eq!(game.clients.len(), 1);
+
+		game.execute_command(CommandSender::Console, "whitelist_remove Alice");
+		assert!(game.clients.is_empty());
+		assert!(alice.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::NotWhitelisted, ..})));
+
+		// staff can always join
+		game.accounts.set_password("Carol", "secret").unwrap();
+		game.accounts.set_role("Carol", Role::Moderator);
+		let carol = join(&connector, "carol", Some("secret"));
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 2);
+		assert_eq!(game.clients.len(), 1);
+		assert!(carol.drain().iter().any(|message| matches!(message, Message::InitClient{..})));
+	}
+
+	#[test]
+	fn test_ip_range_bans() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let _first = connector.connect_from("10.0.3.1:4000".parse().unwrap()).unwrap();
+		let _second = connector.connect_from("10.0.200.7:4000".parse().unwrap()).unwrap();
+		let _other = connector.connect_from("10.1.0.1:4000".parse().unwrap()).unwrap();
+		game.accept_connections(&mut transport);
+
+		game.execute_command(CommandSender::Console, "ban_ip 10.0.0.0/16 proxies");
+		assert_eq!(game.clients.len(), 1);
+		let banned = connector.connect_from("[::ffff:10.0.9.9]:4000".parse().unwrap()).unwrap();
+		game.accept_connections(&mut transport);
+		assert_eq!(game.clients.len(), 1);
+		assert_eq!(banned.drain(), vec![Message::Kick{reason: KickReason::Banned, message: String::from("proxies")}]);
+
+		game.execute_command(CommandSender::Console, "unban_ip 10.0.9.9/16");
+		let _allowed = connector.connect_from("10.0.9.9:4000".parse().unwrap()).unwrap();
+		game.accept_connections(&mut transport);
+		assert_eq!(game.clients.len(), 2);
+	}
+
+	#[test]
+	fn test_world_edit() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let client = join(&connector, "builder", None);
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		client.drain();
+
+		let center: u64 = 9_223_372_036_854_775_808;
+		game.execute_command(CommandSender::Console, &format!("fill {} {} {} {} simple_wall left", center, center, center + 9, center + 1));
+		game.execute_command(CommandSender::Console, &format!("setblock {} {} simple_wall", center + 1000, center));
+		assert_eq!(game.world.block((center + 9, center + 1)), Block::new(BlockCode::SimpleWall, Orientation::Left));
+		game.tick(1);
+
+		// the fill spans two chunks, the block out of view is not sent
+		let chunks: Vec<protocol::block::Chunk> = client.drain().into_iter().filter_map(|message| match message {
+			Message::Chunk(chunk) => Some(chunk),
+			_ => None,
+		}).collect();
+		assert_eq!(chunks.len(), 2);
+		assert!(chunks.iter().all(|chunk| chunk.blocks[1][1] == Block::new(BlockCode::SimpleWall, Orientation::Left)));
+
+		game.execute_command(CommandSender::Console, &format!("replace {} {} {} {} simple_wall simple_slab", center, center, center + 4, center + 1));
+		assert_eq!(game.world.block((center + 4, center)), Block::new(BlockCode::SimpleSlab, Orientation::Left));
+		assert_eq!(game.world.block((center + 5, center)), Block::new(BlockCode::SimpleWall, Orientation::Left));
+
+		game.execute_command(CommandSender::Console, &format!("copy {} {} {} {}", center + 5, center, center + 9, center + 1));
+		game.execute_command(CommandSender::Console, &format!("paste {} {} 90", center + 20, center + 20));
+		assert_eq!(game.world.block((center + 21, center + 24)), Block::new(BlockCode::SimpleWall, Orientation::Up));
+		assert_eq!(game.world.block((center + 22, center + 20)), Block::default());
+	}
+
+	#[test]
+	fn test_backup_restore() {
+		let root = std::env::temp_dir().join(format!("dungeon_restore_{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&root);
+		let mut game = Game::new();
+		game.config.world_path = root.join("world");
+		game.config.bans_path = root.join("bans.yml");
+		game.config.backup_path = root.join("backups");
+		game.world = World::open(&game.config.world_path).unwrap();
+		game.bans = BanList::load(&game.config.bans_path).unwrap();
+		let wall = Block::new(BlockCode::SimpleWall, Orientation::Up);
+
+		game.set_block((0, 0), wall);
+		game.execute_command(CommandSender::Console, "backup");
+		assert!(game.save().is_err());
+		// written once the backup is over
+		game.execute_command(CommandSender::Console, "ban early");
+		assert!(!game.config.bans_path.exists());
+		assert!(game.backups.wait().unwrap().is_ok());
+		game.tick(1);
+		assert!(std::fs::read_to_string(&game.config.bans_path).unwrap().contains("early"));
+		let name = backups::list(&game.config.backup_path).unwrap().remove(0);
+
+		game.set_block((0, 0), Block::default());
+		game.execute_command(CommandSender::Console, "ban griefer");
+		game.save().unwrap();
+
+		let (mut transport, connector) = MemoryTransport::new();
+		let client = connector.connect().unwrap();
+		game.accept_connections(&mut transport);
+		game.execute_command(CommandSender::Console, &format!("restore {}", name));
+		assert!(game.clients.is_empty());
+		assert!(client.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::ServerStopping, ..})));
+		assert_eq!(game.world.block((0, 0)), wall);
+		assert!(game.bans.names.is_empty());
+
+		std::fs::remove_dir_all(&root).unwrap();
+	}
+
+	#[test]
+	fn test_profiles() {
+		let path = std::env::temp_dir().join(format!("dungeon_game_profiles_{}", std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		game.profiles = Profiles::open(&path).unwrap();
+
+		let mut client = connector.connect().unwrap();
+		client.send(&init("Alice", None)).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(0);
+		let id = game.clients[0].id;
+		game.entities.get_mut(&id).unwrap().coords.x.main += 100;
+		client.close();
+		game.tick(1);
+		assert!(game.clients.is_empty());
+
+		// the player comes back where it left, with the same id
+		let client = connector.connect().unwrap();
+		client.send(&init("Alice", None)).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(2);
+		let messages = client.drain();
+		assert!(messages.contains(&Message::InitClient{id}));
+		let player = messages.iter().find_map(|message| match message {
+			Message::CreateEntity(entity) => Some(entity.clone()),
+			_ => None,
+		}).unwrap();
+		assert_eq!(player.coords.x.main, Coords::default().x.main + 100);
+		assert_eq!(game.clients[0].loaded_chunks_top_left, game.config.view_top_left(&player.coords));
+		assert_eq!(game.clients[0].profile.as_ref().unwrap().stats.logins, 2);
+
+		// the same player can't be connected twice
+		let twin = connector.connect().unwrap();
+		twin.send(&init("Alice", None)).unwrap();
+		game.accept_connections(&mut transport);
+		game.tick(3);
+		assert_eq!(game.clients.len(), 1);
+		assert!(twin.drain().iter().any(|message| matches!(message, Message::Kick{..})));
+
+		game.save().unwrap();
+		assert_eq!(game.profiles.load("alice").unwrap().unwrap().id, id);
+		std::fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn test_authentication() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		let login = |password: Option<&str>| join(&connector, "Alice", password);
+
+		let alice = login(Some("secret"));
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 0);
+		assert!(alice.drain().iter().any(|message| matches!(message, Message::InitClient{..})));
+
+		let twin = login(Some("secret"));
+		let thief = login(Some("guess"));
+		let guest = login(None);
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 1);
+		assert_eq!(game.clients.len(), 1);
+		let kick_reason = |messages: Vec<Message>| messages.into_iter().find_map(|message| match message {
+			Message::Kick{reason, ..} => Some(reason),
+			_ => None,
+		});
+		assert_eq!(kick_reason(twin.drain()), Some(KickReason::AlreadyConnected));
+		assert_eq!(kick_reason(thief.drain()), Some(KickReason::AlreadyConnected));
+		assert_eq!(kick_reason(guest.drain()), Some(KickReason::WrongPassword));
+
+		game.config.allow_registration = false;
+		let bob = join(&connector, "Bob", Some("secret"));
+		game.accept_connections(&mut transport);
+		game.tick(2);
+		assert_eq!(kick_reason(bob.drain()), Some(KickReason::UnknownAccount));
+
+		// the username of a guest can't be registered while it is playing
+		game.config.allow_registration = true;
+		let _carol = join(&connector, "Carol", None);
+		game.accept_connections(&mut transport);
+		game.tick(3);
+		let thief = join(&connector, "carol", Some("secret"));
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 4);
+		assert_eq!(kick_reason(thief.drain()), Some(KickReason::AlreadyConnected));
+		assert!(game.accounts.find("carol").is_none());
+
+		// nor by someone who can't join
+		game.whitelist.enabled = true;
+		let dave = join(&connector, "Dave", Some("secret"));
+		game.accept_connections(&mut transport);
+		game.tick(5);
+		assert_eq!(kick_reason(dave.drain()), Some(KickReason::NotWhitelisted));
+		assert!(game.accounts.find("dave").is_none());
+
+		// the password is checked in the background and the first player to be accepted joins
+		game.whitelist.enabled = false;
+		let erin = join(&connector, "Erin", Some("secret"));
+		let twin = join(&connector, "erin", Some("other"));
+		game.accept_connections(&mut transport);
+		game.tick(6);
+		assert!(!erin.drain().iter().any(|message| matches!(message, Message::InitClient{..})));
+		game.passwords.wait();
+		game.tick(7);
+		assert!(erin.drain().iter().any(|message| matches!(message, Message::InitClient{..})));
+		assert_eq!(kick_reason(twin.drain()), Some(KickReason::AlreadyConnected));
+		assert!(game.accounts.find("erin").is_some());
+
+		let thief = join(&connector, "Erin", Some("guess"));
+		game.kick(game.find_player("erin").unwrap(), KickReason::Kicked, "");
+		game.accept_connections(&mut transport);
+		tick_with_logins(&mut game, 8);
+		assert_eq!(kick_reason(thief.drain()), Some(KickReason::WrongPassword));
+	}
+
+	#[test]
+	fn test_invalid_usernames() {
+		let (mut transport, connector) = MemoryTransport::new();
+		let mut game = Game::new();
+		for username in ["", "x", "Bad\nname", "Console"].iter() {
+			let client = join(&connector, username, None);
+			game.accept_connections(&mut transport);
+			game.tick(0);
+			assert!(game.clients.is_empty());
+			assert!(client.drain().iter().any(|message| matches!(message, Message::Kick{reason: KickReason::InvalidUsername, ..})), "{:?} was accepted", username);
+		}
+	}
+}
@@ -0,0 +1,24 @@
+#![allow(dead_code)]
+use core::fmt::Display;
+use chrono::Local;
+
+pub mod accounts;
+pub mod backups;
+pub mod bans;
+pub mod chat;
+pub mod client;
+pub mod commands;
+pub mod config;
+pub mod edit;
+pub mod game;
+pub mod migrations;
+pub mod profiles;
+pub mod rate_limit;
+pub mod tick;
+pub mod transport;
+pub mod whitelist;
+pub mod world;
+
+pub fn log(message: impl Display) {
+	println!("\x1B[90m[{}]\x1B[0m {}", Local::now().format("%T"), message);
+}
@@ -0,0 +1,251 @@
+//! Upgrades of the files written by older versions of the server.
+//!
+//! Every change of a save format must increment its version and register a migration from the previous version,
+//! so that any old save can be upgraded step by step.
+//! A fixture saved with each old version is kept in `server/fixtures` to test these migrations.
+//! The registries can be replaced, so that the upgrade steps are tested even when no format has changed yet.
+
+use std::fs;
+use std::path::Path;
+use serde_yaml::Value;
+use crate::profiles::PROFILE_VERSION;
+use crate::world::{self, World, FORMAT_VERSION};
+
+/// Turn a region file of a version into a region file of the next version.
+pub type RegionMigration = fn(region: (u64, u64), data: &[u8]) -> Result<Vec<u8>, String>;
+
+/// `REGION_MIGRATIONS[n]` upgrades a region file from the version `n + 1` to the version `n + 2`.
+pub const REGION_MIGRATIONS: &[RegionMigration] = &[];
+
+/// Turn a profile of a version into a profile of the next version, except for the version field.
+pub type ProfileMigration = fn(profile: &mut Value) -> Result<(), String>;
+
+/// `PROFILE_MIGRATIONS[n]` upgrades a profile from the version `n + 1` to the version `n + 2`.
+pub const PROFILE_MIGRATIONS: &[ProfileMigration] = &[];
+
+/// Upgrade a profile to the current version.
+pub fn migrate_profile(profile: Value) -> Result<Value, String> {
+	migrate_profile_with(PROFILE_MIGRATIONS, PROFILE_VERSION, profile)
+}
+
+/// Upgrade a profile to `version`, the last of `migrations` upgrading to `version`.
+/// Profiles older than the first migration are not supported.
+pub fn migrate_profile_with(migrations: &[ProfileMigration], version: u16, mut profile: Value) -> Result<Value, String> {
+	let oldest = version as u64 - migrations.len() as u64;
+	let from = match profile.get("version").and_then(Value::as_u64) {
+		Some(from) if from >= oldest && from <= version as u64 => from,
+		Some(from) => return Err(format!("unsupported profile version {} (this server writes the version {})", from, version)),
+		None => return Err(String::from("the profile has no version")),
+	};
+	for migration in &migrations[(from - oldest) as usize..] {
+		migration(&mut profile)?;
+	}
+	if let Value::Mapping(mapping) = &mut profile {
+		mapping.insert(Value::from("version"), Value::from(version));
+	}
+	Ok(profile)
+}
+
+/// Upgrade a region file to the current version.
+/// Also return whether the file needed an upgrade.
+pub fn migrate_region(region: (u64, u64), data: Vec<u8>) -> Result<(Vec<u8>, bool), String> {
+	migrate_region_with(REGION_MIGRATIONS, FORMAT_VERSION, region, data)
+}
+
+/// Upgrade a region file to `version`, the last of `migrations` upgrading to `version`.
+/// Files older than the first migration are not supported.
+pub fn migrate_region_with(migrations: &[RegionMigration], version: u16, region: (u64, u64), mut data: Vec<u8>) -> Result<(Vec<u8>, bool), String> {
+	let oldest = version as usize - migrations.len();
+	let from = world::region_version(&data)?;
+	if (from as usize) < oldest || from > version {
+		return Err(format!("unsupported format version {} (this server writes the version {})", from, version));
+	}
+	for migration in &migrations[from as usize - oldest..] {
+		data = migration(region, &data)?;
+	}
+	Ok((data, from != version))
+}
+
+/// Upgrade every file of a world save in place, and return how many files have been upgraded.
+pub fn migrate_world(path: impl AsRef<Path>) -> Result<usize, String> {
+	migrate_world_with(REGION_MIGRATIONS, path)
+}
+
+/// Upgrade every file of a world save in place to the current version, the last of `migrations` upgrading to it.
+pub fn migrate_world_with(migrations: &[RegionMigration], path: impl AsRef<Path>) -> Result<usize, String> {
+	let path = path.as_ref();
+	let entries = fs::read_dir(path).map_err(|error| format!("can't read the world directory {}: {}", path.display(), error))?;
+
+	let mut migrated = 0;
+	for entry in entries {
+		let file_path = entry.map_err(|error| error.to_string())?.path();
+		let region = match World::parse_region_path(&file_path) {
+			Some(region) => region,
+			None => continue,
+		};
+
+		let data = fs::read(&file_path).map_err(|error| format!("can't read {}: {}", file_path.display(), error))?;
+		let (data, upgraded) = migrate_region_with(migrations, FORMAT_VERSION, region, data).map_err(|error| format!("can't upgrade {}: {}", file_path.display(), error))?;
+		world::decode_region(&data).map_err(|error| format!("the upgrade of {} is invalid: {}", file_path.display(), error))?;
+		if upgraded {
+			let temporary_path = file_path.with_extension("tmp");
+			fs::write(&temporary_path, data)
+				.and_then(|()| fs::rename(&temporary_path, &file_path))
+				.map_err(|error| format!("can't write {}: {}", file_path.display(), error))?;
+			migrated += 1;
+		}
+	}
+	Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use protocol::block::{Block, BlockCode, Orientation};
+
+	fn copy_fixture(name: &str) -> std::path::PathBuf {
+		let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name);
+		let path = std::env::temp_dir().join(format!("dungeon_{}_{}", name, std::process::id()));
+		let _ = fs::remove_dir_all(&path);
+		fs::create_dir_all(&path).unwrap();
+		for entry in fs::read_dir(fixture).unwrap() {
+			let entry = entry.unwrap();
+			fs::copy(entry.path(), path.join(entry.file_name())).unwrap();
+		}
+		path
+	}
+
+	fn check_world_v1(world: &mut World) {
+		assert_eq!(world.block((1, 2)), Block::new(BlockCode::SimpleWall, Orientation::Left));
+		assert_eq!(world.block((8, 8)), Block::new(BlockCode::SimpleSlab, Orientation::Right));
+		assert_eq!(world.block((15, 15)), Block::new(BlockCode::SimpleWall, Orientation::Down));
+		assert_eq!(world.block((2, 2)), Block::default());
+		assert_eq!(world.map().chunks().len(), 2);
+	}
+
+	/// A made-up version 0 of the region files, which had no chunk count.
+	fn region_v0_to_v1(_region: (u64, u64), data: &[u8]) -> Result<Vec<u8>, String> {
+		let count = (data.len() - 6) / (2 + 64 * 3);
+		let mut upgraded = data[..4].to_vec();
+		upgraded.extend_from_slice(&1u16.to_le_bytes());
+		upgraded.extend_from_slice(&(count as u16).to_le_bytes());
+		upgraded.extend_from_slice(&data[6..]);
+		Ok(upgraded)
+	}
+
+	/// A made-up version 2, which appended the number of chunks twice.
+	fn region_v1_to_v2(_region: (u64, u64), data: &[u8]) -> Result<Vec<u8>, String> {
+		let mut upgraded = data.to_vec();
+		upgraded[4] = 2;
+		upgraded.extend_from_slice(&data[6..8]);
+		Ok(upgraded)
+	}
+
+	/// Turn a region file of the version 1 into one of the made-up version 0.
+	fn region_v0(data: &[u8]) -> Vec<u8> {
+		let mut old = data[..4].to_vec();
+		old.extend_from_slice(&0u16.to_le_bytes());
+		old.extend_from_slice(&data[8..]);
+		old
+	}
+
+	/// A made-up version 0 of the profiles, which had no inventory.
+	fn profile_v0_to_v1(profile: &mut Value) -> Result<(), String> {
+		match profile {
+			Value::Mapping(mapping) => {
+				mapping.insert(Value::from("inventory"), Value::Sequence(Vec::new()));
+				Ok(())
+			},
+			_ => Err(String::from("the profile is not a mapping")),
+		}
+	}
+
+	#[test]
+	fn test_migrate_world_v1() {
+		// the current version has nothing to upgrade
+		let path = copy_fixture("world_v1");
+		assert_eq!(migrate_world(&path).unwrap(), 0);
+		assert_eq!(world::region_version(&fs::read(World::region_path(&path, (0, 0))).unwrap()), Ok(FORMAT_VERSION));
+		let mut world = World::open(&path).unwrap();
+		check_world_v1(&mut world);
+		assert!(!world.has_unsaved_changes());
+		fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn test_migrate_world_v0() {
+		let path = copy_fixture("world_v1");
+		let region_path = World::region_path(&path, (0, 0));
+		let current = fs::read(&region_path).unwrap();
+		fs::write(&region_path, region_v0(&current)).unwrap();
+
+		// the file is rewritten once, then is up to date
+		assert!(migrate_world(&path).is_err());
+		assert_eq!(migrate_world_with(&[region_v0_to_v1], &path).unwrap(), 1);
+		assert_eq!(fs::read(&region_path).unwrap(), current);
+		assert_eq!(migrate_world_with(&[region_v0_to_v1], &path).unwrap(), 0);
+
+		// old regions are also upgraded when they are loaded, and written again on the next save
+		fs::write(&region_path, region_v0(&current)).unwrap();
+		let mut world = World::open(&path).unwrap();
+		world.set_migrations(&[region_v0_to_v1]);
+		check_world_v1(&mut world);
+		assert!(world.has_unsaved_changes());
+		world.save().unwrap();
+		assert_eq!(fs::read(&region_path).unwrap(), current);
+		fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn test_migration_steps() {
+		let current = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/world_v1/r.0.0.region")).unwrap();
+		let migrations: &[RegionMigration] = &[region_v0_to_v1, region_v1_to_v2];
+
+		// every step from the version of the file is applied in order
+		let (from_v0, upgraded) = migrate_region_with(migrations, 2, (0, 0), region_v0(&current)).unwrap();
+		assert!(upgraded);
+		let (from_v1, _) = migrate_region_with(migrations, 2, (0, 0), current.clone()).unwrap();
+		assert_eq!(from_v0, from_v1);
+		assert_eq!(world::region_version(&from_v0), Ok(2));
+		assert_eq!(from_v0.len(), current.len() + 2);
+		assert_eq!(migrate_region_with(migrations, 2, (0, 0), from_v0.clone()).unwrap(), (from_v0, false));
+
+		// versions older than the first migration are not supported
+		assert!(migrate_region_with(&[region_v1_to_v2], 2, (0, 0), region_v0(&current)).is_err());
+	}
+
+	#[test]
+	fn test_migrate_profile() {
+		let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/players_v1/alice.yml");
+		let current: Value = serde_yaml::from_str(&fs::read_to_string(&fixture).unwrap()).unwrap();
+		assert_eq!(migrate_profile(current.clone()).unwrap(), current);
+
+		let path = copy_fixture("players_v1");
+		let profile = crate::profiles::Profiles::open(&path).unwrap().load("alice").unwrap().unwrap();
+		assert_eq!((profile.id, profile.stats.logins, profile.coords.x.main), (42, 3, 9_223_372_036_854_775_908));
+		fs::remove_dir_all(&path).unwrap();
+
+		let mut old = current.clone();
+		if let Value::Mapping(mapping) = &mut old {
+			mapping.remove(&Value::from("inventory"));
+			mapping.insert(Value::from("version"), Value::from(0));
+		}
+		assert!(migrate_profile(old.clone()).is_err());
+		assert_eq!(migrate_profile_with(&[profile_v0_to_v1], 1, old).unwrap(), current);
+
+		let mut future = current;
+		if let Value::Mapping(mapping) = &mut future {
+			mapping.insert(Value::from("version"), Value::from(PROFILE_VERSION + 1));
+		}
+		assert!(migrate_profile(future).is_err());
+		assert!(migrate_profile(Value::from("no version")).is_err());
+	}
+
+	#[test]
+	fn test_unsupported_version() {
+		let mut data = fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/world_v1/r.0.0.region")).unwrap();
+		data[4] = 200;
+		assert!(migrate_region((0, 0), data).is_err());
+		assert!(migrate_region((0, 0), b"not a region".to_vec()).is_err());
+	}
+}
@@ -0,0 +1,202 @@
+//! What the server remembers about each player between connections.
+//!
+//! Every player has a YAML file named after its lowercase username.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use serde::{Serialize, Deserialize};
+use protocol::coords::Coords;
+use protocol::entity::{Entity, EntityType};
+use crate::migrations;
+
+/// The version of the profiles written by this server.
+pub const PROFILE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+	/// The version of the format, used to upgrade old profiles.
+	pub version: u16,
+	pub username: String,
+	/// The id of the player entity, which stays the same between connections.
+	pub id: u64,
+	/// Where the player was when it disconnected.
+	pub coords: Coords,
+	pub stats: Stats,
+	/// The names of the items owned by the player. There is no item yet.
+	pub inventory: Vec<String>,
+	/// When the play time has been updated for the last time.
+	#[serde(skip, default = "Instant::now")]
+	last_update: Instant,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+	pub logins: u64,
+	/// Unix timestamp of the first connection.
+	pub first_login: i64,
+	/// Unix timestamp of the last connection.
+	pub last_login: i64,
+	/// Seconds spent connected.
+	pub play_time: u64,
+	/// In blocks.
+	pub distance_walked: f64,
+}
+
+impl Profile {
+	/// Create the profile of a player connecting for the first time.
+	pub fn new(username: &str, id: u64) -> Profile {
+		Profile {
+			version: PROFILE_VERSION,
+			username: username.to_string(),
+			id,
+			coords: Coords::default(),
+			stats: Stats {
+				first_login: Utc::now().timestamp(),
+				..Stats::default()
+			},
+			inventory: Vec::new(),
+			last_update: Instant::now(),
+		}
+	}
+
+	/// Count a new connection.
+	pub fn login(&mut self) {
+		self.stats.logins += 1;
+		self.stats.last_login = Utc::now().timestamp();
+		self.last_update = Instant::now();
+	}
+
+	/// Build the entity of the player.
+	pub fn spawn(&self) -> Entity {
+		Entity::new(self.coords.clone(), self.id, self.username.clone(), EntityType::Player)
+	}
+
+	/// Copy the state of the player entity and count the play time.
+	pub fn update(&mut self, entity: &Entity) {
+		self.coords = entity.coords.clone();
+		// only whole seconds are counted, the remainder is counted on the next update
+		let elapsed = self.last_update.elapsed().as_secs();
+		self.stats.play_time += elapsed;
+		self.last_update += Duration::from_secs(elapsed);
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Profiles {
+	/// The directory of the profiles. Nothing is read nor written when `None`.
+	path: Option<PathBuf>,
+	/// The profiles of disconnected players that could not be written yet.
+	unsaved: Vec<Profile>,
+}
+
+impl Profiles {
+	/// Use the profiles saved in a directory, creating it if needed.
+	pub fn open(path: impl AsRef<Path>) -> Result<Profiles, String> {
+		let path = path.as_ref();
+		fs::create_dir_all(path).map_err(|error| format!("can't create the profile directory {}: {}", path.display(), error))?;
+		Ok(Profiles {
+			path: Some(path.to_path_buf()),
+			unsaved: Vec::new(),
+		})
+	}
+
+	/// The file of a player. Characters that could be unsafe in a file name are escaped.
+	fn profile_path(directory: &Path, username: &str) -> PathBuf {
+		let mut name = String::new();
+		for byte in username.to_lowercase().bytes() {
+			match byte {
+				b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' => name.push(byte as char),
+				byte => name.push_str(&format!("%{:02x}", byte)),
+			}
+		}
+		directory.join(format!("{}.yml", name))
+	}
+
+	/// Return the profile of a player, or `None` if it never connected.
+	pub fn load(&mut self, username: &str) -> Result<Option<Profile>, String> {
+		if let Some(idx) = self.unsaved.iter().position(|profile| profile.username.eq_ignore_ascii_case(username)) {
+			return Ok(Some(self.unsaved.remove(idx)));
+		}
+		let path = match &self.path {
+			Some(directory) => Profiles::profile_path(directory, username),
+			None => return Ok(None),
+		};
+
+		let data = match fs::read_to_string(&path) {
+			Ok(data) => data,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+			Err(error) => return Err(format!("can't read {}: {}", path.display(), error)),
+		};
+		let value = serde_yaml::from_str(&data).map_err(|error| format!("invalid profile {}: {}", path.display(), error))?;
+		let value = migrations::migrate_profile(value).map_err(|error| format!("can't upgrade {}: {}", path.display(), error))?;
+		let profile = serde_yaml::from_value(value).map_err(|error| format!("invalid profile {}: {}", path.display(), error))?;
+		Ok(Some(profile))
+	}
+
+	pub fn save(&self, profile: &Profile) -> Result<(), String> {
+		if let Some(directory) = &self.path {
+			let path = Profiles::profile_path(directory, &profile.username);
+			let temporary_path = path.with_extension("tmp");
+			let data = serde_yaml::to_string(profile).map_err(|error| error.to_string())?;
+			fs::write(&temporary_path, data)
+				.and_then(|()| fs::rename(&temporary_path, &path))
+				.map_err(|error| format!("can't save the profile {}: {}", path.display(), error))?;
+		}
+		Ok(())
+	}
+
+	/// Keep a profile to write it on the next call to `flush`.
+	pub fn save_later(&mut self, profile: Profile) {
+		self.unsaved.retain(|unsaved| !unsaved.username.eq_ignore_ascii_case(&profile.username));
+		self.unsaved.push(profile);
+	}
+
+	/// Write the profiles given to `save_later`.
+	pub fn flush(&mut self) -> Result<(), String> {
+		while let Some(profile) = self.unsaved.last() {
+			self.save(profile)?;
+			self.unsaved.pop();
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_save_and_load() {
+		let path = std::env::temp_dir().join(format!("dungeon_profiles_{}", std::process::id()));
+		let _ = fs::remove_dir_all(&path);
+		let mut profiles = Profiles::open(&path).unwrap();
+		assert_eq!(profiles.load("Alice").unwrap(), None);
+
+		let mut profile = Profile::new("Alice", 42);
+		profile.login();
+		profile.coords.x.main += 5;
+		profile.inventory.push(String::from("torch"));
+		profiles.save(&profile).unwrap();
+		assert_eq!(profiles.load("ALICE").unwrap().unwrap().coords, profile.coords);
+		assert_eq!(profiles.load("alice").unwrap().unwrap().stats, profile.stats);
+
+		// names can't escape from the directory
+		assert_eq!(Profiles::profile_path(&path, "../Bob.x"), path.join("%2e%2e%2fbob%2ex.yml"));
+
+		let mut other = Profile::new("Bob", 7);
+		other.coords.y.main += 1;
+		profiles.save_later(other.clone());
+		assert!(!Profiles::profile_path(&path, "bob").exists());
+		profiles.flush().unwrap();
+		assert_eq!(profiles.load("bob").unwrap().unwrap().id, 7);
+
+		fs::write(Profiles::profile_path(&path, "old"), "username: old\nid: 1\n").unwrap();
+		assert!(profiles.load("old").is_err());
+
+		fs::remove_dir_all(&path).unwrap();
+	}
+}
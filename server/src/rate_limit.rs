@@ -0,0 +1,177 @@
+//! Token buckets limiting how many messages each client can send.
+//!
+//! Every kind of message has its own bucket, so that a flood of chat messages can't prevent a player from moving.
+//! Sending a message takes a token, and tokens come back at a fixed rate up to the size of the bucket.
+
+use std::fmt;
+use std::time::Instant;
+use serde::{Serialize, Deserialize};
+use protocol::message::Message;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+	/// Messages allowed every second on average.
+	pub per_second: f64,
+	/// Messages that can be sent at once after a pause.
+	pub burst: f64,
+}
+
+impl Rate {
+	pub const fn new(per_second: f64, burst: f64) -> Rate {
+		Rate { per_second, burst }
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+	/// The client sends its position on every frame where the player moves.
+	pub movement: Rate,
+	pub chat: Rate,
+	pub ping: Rate,
+	/// Every other message.
+	pub other: Rate,
+}
+
+impl Default for RateLimits {
+	fn default() -> Self {
+		RateLimits {
+			movement: Rate::new(200.0, 100.0),
+			chat: Rate::new(2.0, 5.0),
+			ping: Rate::new(5.0, 10.0),
+			other: Rate::new(2.0, 5.0),
+		}
+	}
+}
+
+impl RateLimits {
+	pub fn validate(&self) -> Result<(), String> {
+		for rate in [self.movement, self.chat, self.ping, self.other].iter() {
+			if !(rate.per_second > 0.0 && rate.burst >= 1.0) {
+				return Err(String::from("rate limits must be positive and allow at least one message at once"));
+			}
+		}
+		Ok(())
+	}
+}
+
+/// The kinds of messages sharing a bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MessageKind {
+	Movement,
+	Chat,
+	Ping,
+	Other,
+}
+
+impl MessageKind {
+	pub fn of(message: &Message) -> MessageKind {
+		match message {
+			Message::TpEntity{..} => MessageKind::Movement,
+			Message::ChatMessage{..} => MessageKind::Chat,
+			Message::Ping(_) | Message::Pong(_) => MessageKind::Ping,
+			_ => MessageKind::Other,
+		}
+	}
+}
+
+impl fmt::Display for MessageKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MessageKind::Movement => write!(f, "movement"),
+			MessageKind::Chat => write!(f, "chat"),
+			MessageKind::Ping => write!(f, "ping"),
+			MessageKind::Other => write!(f, "other"),
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+	rate: Rate,
+	tokens: f64,
+	last_refill: Instant,
+}
+
+impl TokenBucket {
+	/// Create a full bucket.
+	pub fn new(rate: Rate, now: Instant) -> TokenBucket {
+		TokenBucket {
+			rate,
+			tokens: rate.burst,
+			last_refill: now,
+		}
+	}
+
+	/// Take a token if there is one.
+	pub fn take(&mut self, now: Instant) -> bool {
+		let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+		self.tokens = (self.tokens + elapsed * self.rate.per_second).min(self.rate.burst);
+		self.last_refill = now;
+		if self.tokens >= 1.0 {
+			self.tokens -= 1.0;
+			true
+		} else {
+			false
+		}
+	}
+}
+
+/// The buckets of a client.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+	movement: TokenBucket,
+	chat: TokenBucket,
+	ping: TokenBucket,
+	other: TokenBucket,
+}
+
+impl RateLimiter {
+	pub fn new(limits: &RateLimits) -> RateLimiter {
+		let now = Instant::now();
+		RateLimiter {
+			movement: TokenBucket::new(limits.movement, now),
+			chat: TokenBucket::new(limits.chat, now),
+			ping: TokenBucket::new(limits.ping, now),
+			other: TokenBucket::new(limits.other, now),
+		}
+	}
+
+	/// Return whether a message of this kind can be processed now.
+	pub fn allow(&mut self, kind: MessageKind, now: Instant) -> bool {
+		match kind {
+			MessageKind::Movement => self.movement.take(now),
+			MessageKind::Chat => self.chat.take(now),
+			MessageKind::Ping => self.ping.take(now),
+			MessageKind::Other => self.other.take(now),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::time::Duration;
+
+	#[test]
+	fn test_token_bucket() {
+		let start = Instant::now();
+		let mut bucket = TokenBucket::new(Rate::new(2.0, 3.0), start);
+		assert!((0..3).all(|_| bucket.take(start)));
+		assert!(!bucket.take(start));
+		assert!(!bucket.take(start + Duration::from_millis(400)));
+		assert!(bucket.take(start + Duration::from_millis(500)));
+		assert!(!bucket.take(start + Duration::from_millis(500)));
+
+		// tokens don't accumulate beyond the burst
+		let later = start + Duration::from_secs(60);
+		assert!((0..3).all(|_| bucket.take(later)));
+		assert!(!bucket.take(later));
+
+		let mut limiter = RateLimiter::new(&RateLimits::default());
+		let now = Instant::now();
+		while limiter.allow(MessageKind::Chat, now) {}
+		assert!(limiter.allow(MessageKind::Movement, now));
+	}
+}
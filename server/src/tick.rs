@@ -0,0 +1,145 @@
+//! Fixed-rate scheduling of the game loop.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::log;
+
+/// The highest tick rate, so that the period of a tick is at least a millisecond.
+pub const MAX_TICK_RATE: u32 = 1000;
+
+/// How many late ticks are run back to back before giving up and dropping them.
+pub const MAX_CATCH_UP_TICKS: u64 = 10;
+
+/// Runs ticks at a fixed rate, whatever the time spent working between them.
+///
+/// When the loop falls behind, late ticks are run immediately to catch up.
+/// If it falls too far behind, late ticks are dropped instead. Tick numbers
+/// always follow the wall clock, so a dropped tick number is never reused.
+pub struct TickScheduler {
+	period: Duration,
+	next_deadline: Instant,
+	next_tick: u64,
+	dropped_ticks: u64,
+	last_start: Option<Instant>,
+	last_work_time: Duration,
+}
+
+impl TickScheduler {
+	pub fn new(rate: u32) -> Self {
+		Self::starting_at(rate, Instant::now())
+	}
+
+	pub fn starting_at(rate: u32, start: Instant) -> Self {
+		assert!(rate > 0 && rate <= MAX_TICK_RATE, "tick rate must be between 1 and {}", MAX_TICK_RATE);
+		TickScheduler {
+			period: Duration::from_secs(1) / rate,
+			next_deadline: start,
+			next_tick: 0,
+			dropped_ticks: 0,
+			last_start: None,
+			last_work_time: Duration::default(),
+		}
+	}
+
+	pub fn period(&self) -> Duration {
+		self.period
+	}
+
+	/// The number of the next tick to run.
+	pub fn next_tick(&self) -> u64 {
+		self.next_tick
+	}
+
+	/// The total number of ticks dropped because the loop was too late.
+	pub fn dropped_ticks(&self) -> u64 {
+		self.dropped_ticks
+	}
+
+	/// The time spent working during the last tick.
+	pub fn last_work_time(&self) -> Duration {
+		self.last_work_time
+	}
+
+	/// Sleep until the next tick is due and return its number.
+	pub fn wait(&mut self) -> u64 {
+		let now = Instant::now();
+		if let Some(last_start) = self.last_start {
+			self.last_work_time = now - last_start;
+		}
+
+		let tick = loop {
+			match self.poll(Instant::now()) {
+				Ok(tick) => break tick,
+				Err(remaining) => sleep(remaining),
+			}
+		};
+		self.last_start = Some(Instant::now());
+		tick
+	}
+
+	/// Return the number of the tick to run at `now`, or how long to wait before it is due.
+	pub fn poll(&mut self, now: Instant) -> Result<u64, Duration> {
+		if now < self.next_deadline {
+			return Err(self.next_deadline - now);
+		}
+
+		let late_ticks = ((now - self.next_deadline).as_nanos() / self.period.as_nanos()) as u64;
+		if late_ticks > MAX_CATCH_UP_TICKS {
+			log(format!("Can't keep up! Is the server overloaded? Dropping {} ticks", late_ticks));
+			self.dropped_ticks += late_ticks;
+			self.next_tick += late_ticks;
+			self.next_deadline += self.period * late_ticks as u32;
+		}
+
+		let tick = self.next_tick;
+		self.next_tick += 1;
+		self.next_deadline += self.period;
+		Ok(tick)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_on_time() {
+		let start = Instant::now();
+		let mut scheduler = TickScheduler::starting_at(50, start);
+
+		assert_eq!(scheduler.poll(start), Ok(0));
+		assert_eq!(scheduler.poll(start + Duration::from_millis(5)), Err(Duration::from_millis(15)));
+		assert_eq!(scheduler.poll(start + Duration::from_millis(20)), Ok(1));
+		assert_eq!(scheduler.poll(start + Duration::from_millis(41)), Ok(2));
+		assert_eq!(scheduler.poll(start + Duration::from_millis(45)), Err(Duration::from_millis(15)));
+	}
+
+	#[test]
+	fn test_catch_up() {
+		let start = Instant::now();
+		let mut scheduler = TickScheduler::starting_at(50, start);
+		scheduler.poll(start).unwrap();
+
+		// three ticks late: they all run immediately
+		let now = start + Duration::from_millis(70);
+		assert_eq!(scheduler.poll(now), Ok(1));
+		assert_eq!(scheduler.poll(now), Ok(2));
+		assert_eq!(scheduler.poll(now), Ok(3));
+		assert_eq!(scheduler.poll(now), Err(Duration::from_millis(10)));
+		assert_eq!(scheduler.dropped_ticks(), 0);
+	}
+
+	#[test]
+	fn test_drop_ticks() {
+		let start = Instant::now();
+		let mut scheduler = TickScheduler::starting_at(50, start);
+		scheduler.poll(start).unwrap();
+
+		// one second late: the late ticks are dropped but the numbering follows the clock
+		let now = start + Duration::from_millis(1030);
+		assert_eq!(scheduler.poll(now), Ok(51));
+		assert_eq!(scheduler.poll(now), Err(Duration::from_millis(10)));
+		assert_eq!(scheduler.dropped_ticks(), 50);
+		assert_eq!(scheduler.next_tick(), 52);
+	}
+}
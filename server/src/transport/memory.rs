@@ -0,0 +1,197 @@
+//! In-memory transport backed by channels.
+//!
+//! Messages are encoded exactly like on the network, so the server goes
+//! through the same decoding path as with a real websocket.
+//! Messages sent to the client wait in an [`OutboundQueue`] until the client reads them.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use protocol::message::Message;
+use super::queue::OutboundQueue;
+use super::{Connection, Disconnected, InvalidFrame, Transport, DEFAULT_MAX_MESSAGE_SIZE, DEFAULT_MAX_QUEUED_BYTES};
+
+/// The queue of a connection, and the condition notified when it changes.
+type SharedQueue = Arc<(Mutex<OutboundQueue>, Condvar)>;
+
+/// The server side of the memory transport.
+pub struct MemoryTransport {
+	connections: Receiver<MemoryConnection>,
+	/// In bytes. Larger frames are rejected.
+	pub max_message_size: usize,
+	/// In bytes. Connections are closed when their client does not read fast enough to stay below.
+	pub max_queued_bytes: usize,
+}
+
+/// A cloneable handle used to open connections to a [`MemoryTransport`].
+#[derive(Clone)]
+pub struct MemoryConnector {
+	connections: Sender<MemoryConnection>,
+}
+
+/// The server side of a single memory connection.
+pub struct MemoryConnection {
+	queue: SharedQueue,
+	receiver: Option<Receiver<String>>,
+	peer_addr: Option<SocketAddr>,
+	max_message_size: usize,
+}
+
+/// The client side of a single memory connection.
+pub struct MemoryClient {
+	sender: Option<Sender<String>>,
+	queue: SharedQueue,
+}
+
+impl MemoryTransport {
+	pub fn new() -> (MemoryTransport, MemoryConnector) {
+		let (sender, receiver) = channel();
+		let transport = MemoryTransport {
+			connections: receiver,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+			max_queued_bytes: DEFAULT_MAX_QUEUED_BYTES,
+		};
+		(transport, MemoryConnector { connections: sender })
+	}
+}
+
+impl Transport for MemoryTransport {
+	fn accept(&mut self) -> Option<Box<dyn Connection>> {
+		match self.connections.try_recv() {
+			Ok(mut connection) => {
+				connection.max_message_size = self.max_message_size;
+				*connection.queue.0.lock().unwrap() = OutboundQueue::new(self.max_queued_bytes);
+				Some(Box::new(connection))
+			},
+			Err(_) => None,
+		}
+	}
+}
+
+impl MemoryConnector {
+	/// Open a new connection. It will be accepted by the next call to [`Transport::accept`].
+	pub fn connect(&self) -> Result<MemoryClient, Disconnected> {
+		self.open(None)
+	}
+
+	/// Open a new connection pretending to come from an address.
+	pub fn connect_from(&self, peer_addr: SocketAddr) -> Result<MemoryClient, Disconnected> {
+		self.open(Some(peer_addr))
+	}
+
+	fn open(&self, peer_addr: Option<SocketAddr>) -> Result<MemoryClient, Disconnected> {
+		let (client_sender, server_receiver) = channel();
+		let queue = Arc::new((Mutex::new(OutboundQueue::new(DEFAULT_MAX_QUEUED_BYTES)), Condvar::new()));
+
+		let connection = MemoryConnection {
+			queue: Arc::clone(&queue),
+			receiver: Some(server_receiver),
+			peer_addr,
+			max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+		};
+		self.connections.send(connection).map_err(|_| Disconnected)?;
+
+		Ok(MemoryClient {
+			sender: Some(client_sender),
+			queue,
+		})
+	}
+}
+
+impl Connection for MemoryConnection {
+	fn send(&mut self, message: &Message) -> Result<(), Disconnected> {
+		let (queue, condvar) = &*self.queue;
+		let pushed = queue.lock().unwrap().push(message);
+		condvar.notify_all();
+		if pushed { Ok(()) } else { Err(Disconnected) }
+	}
+
+	fn receive(&mut self) -> Result<Option<Result<Message, InvalidFrame>>, Disconnected> {
+		let receiver = self.receiver.as_ref().ok_or(Disconnected)?;
+		match receiver.try_recv() {
+			Ok(data) if data.len() > self.max_message_size => Ok(Some(Err(InvalidFrame::TooLarge))),
+			Ok(data) => Ok(Some(Message::decode(data).map_err(|_| InvalidFrame::Undecodable))),
+			Err(TryRecvError::Empty) => Ok(None),
+			Err(TryRecvError::Disconnected) => Err(Disconnected),
+		}
+	}
+
+	fn close(&mut self) {
+		let (queue, condvar) = &*self.queue;
+		queue.lock().unwrap().close();
+		condvar.notify_all();
+		self.receiver = None;
+	}
+
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		self.peer_addr
+	}
+
+	fn queued_bytes(&self) -> usize {
+		self.queue.0.lock().unwrap().bytes()
+	}
+
+	fn bytes_sent(&self) -> u64 {
+		self.queue.0.lock().unwrap().sent()
+	}
+}
+
+impl Drop for MemoryConnection {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
+
+impl MemoryClient {
+	pub fn send(&self, message: &Message) -> Result<(), Disconnected> {
+		self.send_raw(message.encode())
+	}
+
+	/// Send a raw frame, which is not required to be a valid message.
+	pub fn send_raw(&self, data: String) -> Result<(), Disconnected> {
+		match &self.sender {
+			Some(sender) => sender.send(data).map_err(|_| Disconnected),
+			None => Err(Disconnected),
+		}
+	}
+
+	/// Return the next message sent by the server if there is one, without blocking.
+	pub fn try_receive(&self) -> Result<Option<Message>, Disconnected> {
+		self.receive_timeout(Duration::from_secs(0))
+	}
+
+	/// Wait for the next message sent by the server.
+	pub fn receive_timeout(&self, timeout: Duration) -> Result<Option<Message>, Disconnected> {
+		let deadline = Instant::now() + timeout;
+		let (queue, condvar) = &*self.queue;
+		let mut queue = queue.lock().unwrap();
+		loop {
+			if let Some(data) = queue.pop() {
+				return Ok(Some(Message::decode(data).expect("the server sent an invalid message")));
+			}
+			if queue.is_closed() {
+				return Err(Disconnected);
+			}
+			let now = Instant::now();
+			if now >= deadline {
+				return Ok(None);
+			}
+			queue = condvar.wait_timeout(queue, deadline - now).unwrap().0;
+		}
+	}
+
+	/// Return every message currently waiting, without blocking.
+	pub fn drain(&self) -> Vec<Message> {
+		let mut messages = Vec::new();
+		while let Ok(Some(message)) = self.try_receive() {
+			messages.push(message);
+		}
+		messages
+	}
+
+	/// Close the client side of the connection.
+	pub fn close(&mut self) {
+		self.sender = None;
+	}
+}
@@ -0,0 +1,73 @@
+//! Connection handling, independent from the underlying network.
+//!
+//! The game loop only sees [`Transport`]s producing [`Connection`]s.
+//! The websocket backend is used by the real server and the memory backend
+//! allows tests and bots to connect to a server loop without opening a port.
+
+pub mod memory;
+pub mod queue;
+pub mod websocket;
+
+use std::fmt;
+use std::net::SocketAddr;
+use protocol::message::Message;
+
+pub use memory::{MemoryClient, MemoryConnector, MemoryTransport};
+pub use websocket::WebSocketTransport;
+
+/// The size of the largest message accepted by default, in bytes.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024;
+
+/// The size of the messages that can wait to be sent to a client by default, in bytes.
+pub const DEFAULT_MAX_QUEUED_BYTES: usize = 4 * 1024 * 1024;
+
+/// Returned when the other side of a connection is gone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Disconnected;
+
+/// A frame received from a client which is not a valid message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvalidFrame {
+	/// Larger than the maximum message size of the transport.
+	TooLarge,
+	/// Not a message of the protocol.
+	Undecodable,
+}
+
+impl fmt::Display for InvalidFrame {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			InvalidFrame::TooLarge => write!(f, "message too large"),
+			InvalidFrame::Undecodable => write!(f, "invalid message"),
+		}
+	}
+}
+
+/// A source of incoming connections.
+pub trait Transport {
+	/// Return a newly accepted connection if there is one, without blocking.
+	fn accept(&mut self) -> Option<Box<dyn Connection>>;
+}
+
+/// A bidirectional message stream with a single client.
+pub trait Connection: Send {
+	/// Queue a message for the client.
+	/// Fails and closes the connection if the client does not read its messages fast enough to keep the queue bounded.
+	fn send(&mut self, message: &Message) -> Result<(), Disconnected>;
+
+	/// Return the next received message if there is one, without blocking.
+	/// Invalid frames are returned too, so that the game can punish them.
+	fn receive(&mut self) -> Result<Option<Result<Message, InvalidFrame>>, Disconnected>;
+
+	/// Close the connection. Any later call to `send` or `receive` will fail.
+	fn close(&mut self);
+
+	/// The address of the client, if the transport has one.
+	fn peer_addr(&self) -> Option<SocketAddr>;
+
+	/// The size of the messages waiting to be sent.
+	fn queued_bytes(&self) -> usize;
+
+	/// The size of the messages sent since the connection was opened.
+	fn bytes_sent(&self) -> u64;
+}
@@ -0,0 +1,193 @@
+//! The messages waiting to be written to a client.
+//!
+//! The queue is shared by the game loop, which pushes encoded messages, and the writer of the connection.
+//! It is bounded in bytes, and messages made useless by a newer one are dropped instead of piling up.
+
+use std::collections::{HashMap, VecDeque};
+use protocol::message::Message;
+
+/// Messages of the same key are superseded by the newest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Key {
+	/// The position of an entity.
+	Position(u64),
+	Tick,
+}
+
+impl Key {
+	fn of(message: &Message) -> Option<Key> {
+		match message {
+			Message::TpEntity{id, ..} => Some(Key::Position(*id)),
+			Message::Tick(_) => Some(Key::Tick),
+			_ => None,
+		}
+	}
+}
+
+/// Superseded messages are compacted away once they are more than this and more than the half of the queue.
+const MAX_STALE: usize = 64;
+
+#[derive(Debug)]
+struct Entry {
+	key: Option<Key>,
+	seq: u64,
+	data: String,
+}
+
+#[derive(Debug)]
+pub struct OutboundQueue {
+	messages: VecDeque<Entry>,
+	/// The sequence number and the size of the newest queued message of each key.
+	/// The older messages of a key are stale: they are skipped by the writer and not counted in `bytes`.
+	latest: HashMap<Key, (u64, usize)>,
+	stale: usize,
+	next_seq: u64,
+	/// The size of the queued messages.
+	bytes: usize,
+	/// The maximum size of the queued messages.
+	limit: usize,
+	/// The size of the messages taken by the writer.
+	sent: u64,
+	closed: bool,
+}
+
+impl OutboundQueue {
+	pub fn new(limit: usize) -> OutboundQueue {
+		OutboundQueue {
+			messages: VecDeque::new(),
+			latest: HashMap::new(),
+			stale: 0,
+			next_seq: 0,
+			bytes: 0,
+			limit,
+			sent: 0,
+			closed: false,
+		}
+	}
+
+	/// Queue a message, superseding the queued message of the same key.
+	/// If the queue would exceed its limit, it is emptied and closed, and false is returned.
+	pub fn push(&mut self, message: &Message) -> bool {
+		if self.closed {
+			return false;
+		}
+		let key = Key::of(message);
+		let data = message.encode();
+		let replaced = key.and_then(|key| self.latest.get(&key)).map(|(_, len)| *len).unwrap_or(0);
+		if self.bytes - replaced + data.len() > self.limit {
+			self.messages.clear();
+			self.latest.clear();
+			self.stale = 0;
+			self.bytes = 0;
+			self.closed = true;
+			return false;
+		}
+
+		self.bytes = self.bytes - replaced + data.len();
+		let seq = self.next_seq;
+		self.next_seq += 1;
+		if let Some(key) = key {
+			if self.latest.insert(key, (seq, data.len())).is_some() {
+				self.stale += 1;
+			}
+		}
+		self.messages.push_back(Entry { key, seq, data });
+
+		if self.stale > MAX_STALE && self.stale > self.messages.len() / 2 {
+			let latest = &self.latest;
+			self.messages.retain(|entry| match entry.key {
+				Some(key) => latest.get(&key).map(|(seq, _)| *seq) == Some(entry.seq),
+				None => true,
+			});
+			self.stale = 0;
+		}
+		true
+	}
+
+	/// Take the oldest message to write it.
+	pub fn pop(&mut self) -> Option<String> {
+		while let Some(entry) = self.messages.pop_front() {
+			if let Some(key) = entry.key {
+				match self.latest.get(&key) {
+					Some((seq, _)) if *seq == entry.seq => {
+						self.latest.remove(&key);
+					},
+					_ => {
+						self.stale -= 1;
+						continue;
+					},
+				}
+			}
+			self.bytes -= entry.data.len();
+			self.sent += entry.data.len() as u64;
+			return Some(entry.data);
+		}
+		None
+	}
+
+	/// Refuse any new message. The queued messages can still be taken.
+	pub fn close(&mut self) {
+		self.closed = true;
+	}
+
+	pub fn is_closed(&self) -> bool {
+		self.closed
+	}
+
+	pub fn bytes(&self) -> usize {
+		self.bytes
+	}
+
+	pub fn sent(&self) -> u64 {
+		self.sent
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use protocol::coords::Coords;
+
+	#[test]
+	fn test_queue() {
+		let mut queue = OutboundQueue::new(1000);
+		let mut coords = Coords::default();
+		assert!(queue.push(&Message::TpEntity{id: 1, coords: coords.clone()}));
+		assert!(queue.push(&Message::RemoveEntity{id: 2}));
+		assert!(queue.push(&Message::TpEntity{id: 2, coords: coords.clone()}));
+		coords.x.main += 1;
+		assert!(queue.push(&Message::TpEntity{id: 1, coords: coords.clone()}));
+		assert!(queue.push(&Message::Tick(1)));
+		assert!(queue.push(&Message::Tick(2)));
+
+		let bytes = queue.bytes();
+		let messages: Vec<Message> = std::iter::from_fn(|| queue.pop()).map(|data| Message::decode(data).unwrap()).collect();
+		assert_eq!(messages, vec![
+			Message::RemoveEntity{id: 2},
+			Message::TpEntity{id: 2, coords: Coords::default()},
+			Message::TpEntity{id: 1, coords},
+			Message::Tick(2),
+		]);
+		assert_eq!(queue.bytes(), 0);
+		assert_eq!(queue.sent(), bytes as u64);
+
+		// superseded messages don't pile up
+		let mut queue = OutboundQueue::new(1000);
+		let mut coords = Coords::default();
+		for main in 0..10_000 {
+			coords.x.main = main;
+			assert!(queue.push(&Message::TpEntity{id: 1, coords: coords.clone()}));
+		}
+		assert!(queue.messages.len() <= 2 * MAX_STALE + 2);
+		assert_eq!(queue.bytes(), Message::TpEntity{id: 1, coords: coords.clone()}.encode().len());
+		assert_eq!(queue.pop().map(|data| Message::decode(data).unwrap()), Some(Message::TpEntity{id: 1, coords}));
+		assert_eq!(queue.pop(), None);
+
+		let chat = Message::ChatMessage{sender_id: 0, sender_name: String::new(), receiver_id: 0, receiver_name: String::new(), message: "a".repeat(600)};
+		assert!(queue.push(&chat));
+		assert!(!queue.push(&chat));
+		assert!(queue.is_closed());
+		assert_eq!(queue.pop(), None);
+		assert!(!queue.push(&Message::Tick(3)));
+	}
+}
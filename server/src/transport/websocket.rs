@@ -0,0 +1,258 @@
+//! Websocket transport, used by the real server.
+//!
+//! Sockets are driven by a tokio runtime owned by the transport.
+//! Every connection gets a reader task and a writer task. The writer task
+//! sleeps until the game loop queues a message, so idle clients cost nothing.
+//! Messages wait in a bounded [`OutboundQueue`] while the socket is busy.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio_tungstenite::tungstenite::error::{CapacityError, Error as WebSocketError};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message as Frame;
+use futures_util::{SinkExt, StreamExt};
+use protocol::message::Message;
+use crate::log;
+use super::queue::OutboundQueue;
+use super::{Connection, Disconnected, InvalidFrame, Transport};
+
+pub const PROTOCOL_NAME: &str = "dungeon_game_protocol";
+
+pub struct WebSocketTransport {
+	runtime: Runtime,
+	local_addr: SocketAddr,
+	connections: UnboundedReceiver<WebSocketConnection>,
+	/// The number of writer tasks still running.
+	writers: Arc<AtomicUsize>,
+}
+
+pub struct WebSocketConnection {
+	queue: Arc<Mutex<OutboundQueue>>,
+	/// Wakes the writer task up when the queue changes.
+	notify: Arc<Notify>,
+	receiver: UnboundedReceiver<Result<Message, InvalidFrame>>,
+	peer_addr: SocketAddr,
+}
+
+impl WebSocketTransport {
+	/// Bind a websocket server and start accepting connections in the background.
+	/// Messages larger than `max_message_size` bytes are rejected without being fully read,
+	/// and connections are closed when more than `max_queued_bytes` bytes are waiting to be sent.
+	pub fn bind(address: impl ToSocketAddrs, max_message_size: usize, max_queued_bytes: usize) -> io::Result<Self> {
+		let runtime = tokio::runtime::Builder::new_multi_thread()
+			.enable_io()
+			.thread_name("network")
+			.build()?;
+		let listener = std::net::TcpListener::bind(address)?;
+		listener.set_nonblocking(true)?;
+		let local_addr = listener.local_addr()?;
+		let listener = {
+			let _guard = runtime.enter();
+			TcpListener::from_std(listener)?
+		};
+		let (connections_tx, connections_rx) = unbounded_channel();
+		let writers = Arc::new(AtomicUsize::new(0));
+		let writers2 = Arc::clone(&writers);
+
+		runtime.spawn(async move {
+			loop {
+				let (stream, peer_addr) = match listener.accept().await {
+					Ok(client) => client,
+					Err(error) => {
+						log(format!("cannot accept client: {}", error));
+						continue;
+					}
+				};
+				let connections_tx = connections_tx.clone();
+				let writers = Arc::clone(&writers2);
+				tokio::spawn(async move {
+					if let Some(connection) = handle_connection(stream, peer_addr, max_message_size, max_queued_bytes, writers).await {
+						let _ = connections_tx.send(connection);
+					}
+				});
+			}
+		});
+
+		Ok(WebSocketTransport {
+			runtime,
+			local_addr,
+			connections: connections_rx,
+			writers,
+		})
+	}
+
+	pub fn local_addr(&self) -> SocketAddr {
+		self.local_addr
+	}
+
+	/// Wait for the messages queued on closed connections to be sent, then stop every task.
+	pub fn shutdown(self, timeout: Duration) {
+		let deadline = Instant::now() + timeout;
+		while self.writers.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+			sleep(Duration::from_millis(10));
+		}
+		self.runtime.shutdown_timeout(Duration::from_millis(100));
+	}
+}
+
+/// Answer with our protocol name when the client asks for it, as browsers require.
+#[allow(clippy::result_large_err)]
+fn select_protocol(request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+	let requested = request.headers().get("Sec-WebSocket-Protocol").and_then(|value| value.to_str().ok());
+	if requested.map(|protocols| protocols.split(',').any(|protocol| protocol.trim() == PROTOCOL_NAME)).unwrap_or(false) {
+		response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static(PROTOCOL_NAME));
+	}
+	Ok(response)
+}
+
+/// Perform the websocket handshake and spawn the tasks of a connection.
+async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr, max_message_size: usize, max_queued_bytes: usize, writers: Arc<AtomicUsize>) -> Option<WebSocketConnection> {
+	let _ = stream.set_nodelay(true);
+	let config = WebSocketConfig {
+		max_message_size: Some(max_message_size),
+		max_frame_size: Some(max_message_size),
+		..WebSocketConfig::default()
+	};
+	let websocket = match tokio_tungstenite::accept_hdr_async_with_config(stream, select_protocol, Some(config)).await {
+		Ok(websocket) => websocket,
+		Err(_) => {
+			log("cannot accept client");
+			return None;
+		}
+	};
+	let (mut sink, mut stream) = websocket.split();
+	let queue = Arc::new(Mutex::new(OutboundQueue::new(max_queued_bytes)));
+	let notify = Arc::new(Notify::new());
+	let (incoming_tx, incoming_rx) = unbounded_channel();
+
+	writers.fetch_add(1, Ordering::SeqCst);
+	let writer_queue = Arc::clone(&queue);
+	let writer_notify = Arc::clone(&notify);
+	tokio::spawn(async move {
+		loop {
+			let next = {
+				let mut queue = writer_queue.lock().unwrap();
+				match queue.pop() {
+					Some(data) => Some(data),
+					None if queue.is_closed() => break,
+					None => None,
+				}
+			};
+			match next {
+				Some(data) => {
+					if sink.send(Frame::Text(data)).await.is_err() {
+						writer_queue.lock().unwrap().close();
+						break;
+					}
+				},
+				None => writer_notify.notified().await,
+			}
+		}
+		let _ = sink.send(Frame::Close(None)).await;
+		let _ = sink.close().await;
+		writers.fetch_sub(1, Ordering::SeqCst);
+	});
+
+	tokio::spawn(async move {
+		while let Some(frame) = stream.next().await {
+			match frame {
+				Ok(Frame::Close(_)) => {
+					log("Client disconnected");
+					return;
+				},
+				Ok(Frame::Text(data)) => {
+					let message = Message::decode(data).map_err(|_| InvalidFrame::Undecodable);
+					if incoming_tx.send(message).is_err() {
+						return;
+					}
+				},
+				Ok(Frame::Binary(_)) => {
+					if incoming_tx.send(Err(InvalidFrame::Undecodable)).is_err() {
+						return;
+					}
+				},
+				Ok(_) => (),
+				// the rest of the message can't be skipped, so the connection is lost
+				Err(WebSocketError::Capacity(CapacityError::MessageTooLong{..})) => {
+					let _ = incoming_tx.send(Err(InvalidFrame::TooLarge));
+					return;
+				},
+				Err(_) => {
+					log("Client was disconnect unproperly");
+					return;
+				},
+			}
+		}
+	});
+
+	Some(WebSocketConnection {
+		queue,
+		notify,
+		receiver: incoming_rx,
+		peer_addr,
+	})
+}
+
+impl Transport for WebSocketTransport {
+	fn accept(&mut self) -> Option<Box<dyn Connection>> {
+		match self.connections.try_recv() {
+			Ok(connection) => Some(Box::new(connection)),
+			Err(_) => None,
+		}
+	}
+}
+
+impl Connection for WebSocketConnection {
+	fn send(&mut self, message: &Message) -> Result<(), Disconnected> {
+		let pushed = self.queue.lock().unwrap().push(message);
+		self.notify.notify_one();
+		if pushed { Ok(()) } else { Err(Disconnected) }
+	}
+
+	fn receive(&mut self) -> Result<Option<Result<Message, InvalidFrame>>, Disconnected> {
+		if self.queue.lock().unwrap().is_closed() {
+			return Err(Disconnected);
+		}
+		match self.receiver.try_recv() {
+			Ok(message) => Ok(Some(message)),
+			Err(TryRecvError::Empty) => Ok(None),
+			Err(TryRecvError::Disconnected) => Err(Disconnected),
+		}
+	}
+
+	fn close(&mut self) {
+		// The writer task sends the queued messages, then a close frame.
+		self.queue.lock().unwrap().close();
+		self.notify.notify_one();
+		self.receiver.close();
+	}
+
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		Some(self.peer_addr)
+	}
+
+	fn queued_bytes(&self) -> usize {
+		self.queue.lock().unwrap().bytes()
+	}
+
+	fn bytes_sent(&self) -> u64 {
+		self.queue.lock().unwrap().sent()
+	}
+}
+
+impl Drop for WebSocketConnection {
+	fn drop(&mut self) {
+		self.close();
+	}
+}
@@ -0,0 +1,85 @@
+//! The only players allowed to join the server when the whitelist is enabled.
+//!
+//! Moderators and operators can always join.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Whitelist {
+	pub enabled: bool,
+	/// Lowercase usernames.
+	pub names: Vec<String>,
+	/// Where the whitelist is saved. Nothing is saved when `None`.
+	#[serde(skip)]
+	path: Option<PathBuf>,
+	/// Whether the whitelist has changed since it was saved.
+	#[serde(skip)]
+	unsaved: bool,
+}
+
+impl Whitelist {
+	/// Load the whitelist saved at `path`, or create an empty and disabled one saved there.
+	pub fn load(path: impl AsRef<Path>) -> Result<Whitelist, String> {
+		let path = path.as_ref();
+		let mut whitelist = match fs::read_to_string(path) {
+			Ok(data) => serde_yaml::from_str(&data).map_err(|error| format!("invalid whitelist {}: {}", path.display(), error))?,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => Whitelist::default(),
+			Err(error) => return Err(format!("can't read {}: {}", path.display(), error)),
+		};
+		whitelist.path = Some(path.to_path_buf());
+		Ok(whitelist)
+	}
+
+	pub fn save(&self) -> Result<(), String> {
+		if let Some(path) = &self.path {
+			let temporary_path = path.with_extension("tmp");
+			let data = serde_yaml::to_string(self).map_err(|error| error.to_string())?;
+			fs::write(&temporary_path, data)
+				.and_then(|()| fs::rename(&temporary_path, path))
+				.map_err(|error| format!("can't save the whitelist: {}", error))?;
+		}
+		Ok(())
+	}
+
+	/// Save the whitelist if it has changed.
+	pub fn flush(&mut self) -> Result<(), String> {
+		if self.unsaved {
+			self.save()?;
+			self.unsaved = false;
+		}
+		Ok(())
+	}
+
+	pub fn set_enabled(&mut self, enabled: bool) {
+		self.unsaved |= self.enabled != enabled;
+		self.enabled = enabled;
+	}
+
+	/// Whether a username is listed, whatever its case. Does not depend on `enabled`.
+	pub fn contains(&self, username: &str) -> bool {
+		let username = username.to_lowercase();
+		self.names.contains(&username)
+	}
+
+	/// Return false if the name was already listed.
+	pub fn add(&mut self, username: &str) -> bool {
+		if self.contains(username) {
+			return false;
+		}
+		self.names.push(username.to_lowercase());
+		self.unsaved = true;
+		true
+	}
+
+	/// Return false if the name was not listed.
+	pub fn remove(&mut self, username: &str) -> bool {
+		let username = username.to_lowercase();
+		let len = self.names.len();
+		self.names.retain(|name| *name != username);
+		self.unsaved |= self.names.len() != len;
+		self.names.len() != len
+	}
+}
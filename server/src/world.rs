@@ -0,0 +1,444 @@
+//! The map of the server and its save on disk.
+//!
+//! Chunks are grouped in regions of 32x32 chunks, each region being saved in its own file.
+//! A region is read the first time one of its blocks is needed,
+//! and written again when one of its blocks has been modified.
+//! Regions no player can see are removed from memory after a delay.
+//!
+//! A region file contains, in little endian:
+//! - the magic bytes `DGRG`
+//! - the format version, as a u16
+//! - the number of chunks, as a u16
+//! - for each chunk, its index in the region (`x + y * 32`, in chunks) as a u16,
+//!   then its 64 blocks in the order of `Chunk::blocks`, each being a block code (u16) and an orientation (u8)
+//!
+//! Files written by older versions are upgraded by the `migrations` module.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use protocol::block::{Block, BlockCode, Chunk, Orientation};
+use protocol::map::Map;
+use crate::log;
+use crate::migrations::{self, RegionMigration};
+
+/// The width and height of a region, in blocks.
+pub const REGION_SIZE: u64 = 32 * 8;
+/// The version of the region files written by this server.
+pub const FORMAT_VERSION: u16 = 1;
+const MAGIC: &[u8; 4] = b"DGRG";
+const CHUNK_BYTES: usize = 2 + 64 * 3;
+const HEADER_BYTES: usize = 4 + 2 + 2;
+
+pub struct World {
+	map: Map,
+	/// The directory of the save. Nothing is read nor written when `None`.
+	path: Option<PathBuf>,
+	/// The regions already read from the disk, or found missing.
+	loaded_regions: HashSet<(u64, u64)>,
+	/// The regions modified since they have been saved.
+	dirty_regions: HashSet<(u64, u64)>,
+	/// The number of players seeing each loaded region, and since when nobody sees it.
+	viewers: HashMap<(u64, u64), (usize, Instant)>,
+	/// The upgrades applied to old region files when they are read.
+	migrations: &'static [RegionMigration],
+}
+
+impl Default for World {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl World {
+	/// Create a world that is never saved, containing only the spawn.
+	pub fn new() -> World {
+		let mut world = World {
+			map: Map::new(),
+			path: None,
+			loaded_regions: HashSet::new(),
+			dirty_regions: HashSet::new(),
+			viewers: HashMap::new(),
+			migrations: migrations::REGION_MIGRATIONS,
+		};
+		world.generate_spawn();
+		world
+	}
+
+	/// Open the world saved in a directory. A new world is created if the directory contains no region.
+	pub fn open(path: impl AsRef<Path>) -> Result<World, String> {
+		let path = path.as_ref();
+		fs::create_dir_all(path).map_err(|error| format!("can't create the world directory {}: {}", path.display(), error))?;
+		let entries = fs::read_dir(path).map_err(|error| format!("can't read the world directory {}: {}", path.display(), error))?;
+		let is_empty = !entries.filter_map(Result::ok).any(|entry| entry.path().extension().is_some_and(|extension| extension == "region"));
+
+		let mut world = World {
+			map: Map::new(),
+			path: Some(path.to_path_buf()),
+			loaded_regions: HashSet::new(),
+			dirty_regions: HashSet::new(),
+			viewers: HashMap::new(),
+			migrations: migrations::REGION_MIGRATIONS,
+		};
+		if is_empty {
+			log(format!("Creating a new world in {}", path.display()));
+			world.generate_spawn();
+		}
+		Ok(world)
+	}
+
+	fn generate_spawn(&mut self) {
+		let wall = Block::new(BlockCode::SimpleWall, Orientation::Up);
+		for x in 0..12 {
+			self.set_block((9_223_372_036_854_775_810 + x, 9_223_372_036_854_775_807), wall);
+		}
+		for y in 0..8 {
+			self.set_block((9_223_372_036_854_775_810, 9_223_372_036_854_775_807 + y), wall);
+			self.set_block((9_223_372_036_854_775_810 + 11, 9_223_372_036_854_775_807 + y), wall);
+		}
+		self.set_block((9_223_372_036_854_775_890, 9_223_372_036_854_775_807), wall);
+	}
+
+	pub fn block(&mut self, (x, y): (u64, u64)) -> Block {
+		if let Err(error) = self.load_region((x / REGION_SIZE, y / REGION_SIZE)) {
+			log(error);
+		}
+		self.map[(x, y)]
+	}
+
+	/// Return false if the block was already there, or if its region can't be read.
+	pub fn set_block(&mut self, (x, y): (u64, u64), block: Block) -> bool {
+		let region = (x / REGION_SIZE, y / REGION_SIZE);
+		// saving the modified region would overwrite the file which could not be read
+		if let Err(error) = self.load_region(region) {
+			log(error);
+			return false;
+		}
+		if self.map[(x, y)] == block {
+			return false;
+		}
+		self.map[(x, y)] = block;
+		self.dirty_regions.insert(region);
+		true
+	}
+
+	pub fn get_chunk(&mut self, x: u64, y: u64) -> Chunk {
+		if let Err(error) = self.load_region((x / REGION_SIZE, y / REGION_SIZE)) {
+			log(error);
+		}
+		self.map.get_chunk(x, y)
+	}
+
+	/// Replace the upgrades of the old region files, to test them.
+	pub fn set_migrations(&mut self, migrations: &'static [RegionMigration]) {
+		self.migrations = migrations;
+	}
+
+	/// The chunks currently in memory.
+	pub fn map(&self) -> &Map {
+		&self.map
+	}
+
+	pub fn region_path(directory: &Path, (x, y): (u64, u64)) -> PathBuf {
+		directory.join(format!("r.{}.{}.region", x, y))
+	}
+
+	/// Return the coordinates of a region from the name of its file.
+	pub fn parse_region_path(path: &Path) -> Option<(u64, u64)> {
+		let name = path.file_name()?.to_str()?;
+		let mut parts = name.strip_prefix("r.")?.strip_suffix(".region")?.split('.');
+		let region = (parts.next()?.parse().ok()?, parts.next()?.parse().ok()?);
+		match parts.next() {
+			Some(_) => None,
+			None => Some(region),
+		}
+	}
+
+	/// Read a region if it has not been read yet.
+	/// Invalid files are moved aside, but the region is read again on the next call if the file can't be read.
+	fn load_region(&mut self, region: (u64, u64)) -> Result<(), String> {
+		if self.loaded_regions.contains(&region) {
+			return Ok(());
+		}
+		let path = match &self.path {
+			Some(directory) => World::region_path(directory, region),
+			None => return Ok(()),
+		};
+
+		let data = match fs::read(&path) {
+			Ok(data) => data,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => {
+				self.loaded_regions.insert(region);
+				return Ok(());
+			},
+			Err(error) => return Err(format!("can't read the region {}: {}", path.display(), error)),
+		};
+		self.loaded_regions.insert(region);
+		match migrations::migrate_region_with(self.migrations, FORMAT_VERSION, region, data).and_then(|(data, migrated)| Ok((decode_region(&data)?, migrated))) {
+			Ok((chunks, migrated)) => {
+				for chunk in chunks {
+					self.map.set_chunk(region.0 * REGION_SIZE + chunk.x, region.1 * REGION_SIZE + chunk.y, chunk.blocks);
+				}
+				if migrated {
+					self.dirty_regions.insert(region);
+				}
+			},
+			Err(error) => {
+				// keep the file aside instead of overwriting it on the next save
+				let broken_path = path.with_extension("broken");
+				log(format!("can't load the region {}: {}; it has been moved to {}", path.display(), error, broken_path.display()));
+				let _ = fs::rename(&path, broken_path);
+			},
+		}
+		Ok(())
+	}
+
+	/// Write the modified regions to the disk and return how many regions have been written.
+	pub fn save(&mut self) -> Result<usize, String> {
+		let mut saved = 0;
+		for region in self.dirty_regions.iter().copied().collect::<Vec<_>>() {
+			self.save_region(region)?;
+			saved += 1;
+		}
+		Ok(saved)
+	}
+
+	fn save_region(&mut self, region: (u64, u64)) -> Result<(), String> {
+		if let Some(directory) = &self.path {
+			let path = World::region_path(directory, region);
+			let temporary_path = path.with_extension("tmp");
+			let data = encode_region(&self.map, region);
+			fs::write(&temporary_path, data)
+				.and_then(|()| fs::rename(&temporary_path, &path))
+				.map_err(|error| format!("can't save the region {}: {}", path.display(), error))?;
+		}
+		self.dirty_regions.remove(&region);
+		Ok(())
+	}
+
+	/// Count the players seeing each region, then save and remove from memory the regions nobody has seen for `delay`.
+	///
+	/// `views` are the areas seen by the players, as their top left corner and their size in blocks.
+	/// Nothing is removed from a world that is not saved on the disk.
+	/// Returns how many regions have been unloaded.
+	pub fn unload_unseen(&mut self, views: impl IntoIterator<Item = ((u64, u64), (u64, u64))>, delay: Duration) -> Result<usize, String> {
+		if self.path.is_none() {
+			return Ok(0);
+		}
+
+		let now = Instant::now();
+		for (count, _) in self.viewers.values_mut() {
+			*count = 0;
+		}
+		for ((left, top), (width, height)) in views {
+			for region_x in left / REGION_SIZE..=left.saturating_add(width.max(1) - 1) / REGION_SIZE {
+				for region_y in top / REGION_SIZE..=top.saturating_add(height.max(1) - 1) / REGION_SIZE {
+					self.viewers.entry((region_x, region_y)).or_insert((0, now)).0 += 1;
+				}
+			}
+		}
+		for region in self.loaded_regions.iter() {
+			self.viewers.entry(*region).or_insert((0, now));
+		}
+		for (count, unseen_since) in self.viewers.values_mut() {
+			if *count > 0 {
+				*unseen_since = now;
+			}
+		}
+
+		let expired: Vec<(u64, u64)> = self.viewers.iter()
+			.filter(|(_, (count, unseen_since))| *count == 0 && now - *unseen_since >= delay)
+			.map(|(region, _)| *region)
+			.collect();
+		let mut unloaded = 0;
+		for region in expired {
+			if self.dirty_regions.contains(&region) {
+				self.save_region(region)?;
+			}
+			self.viewers.remove(&region);
+			if self.loaded_regions.remove(&region) {
+				self.map.retain_chunks(|chunk| (chunk.x / REGION_SIZE, chunk.y / REGION_SIZE) != region);
+				unloaded += 1;
+			}
+		}
+		Ok(unloaded)
+	}
+
+	pub fn has_unsaved_changes(&self) -> bool {
+		!self.dirty_regions.is_empty()
+	}
+}
+
+fn encode_orientation(orientation: Orientation) -> u8 {
+	match orientation {
+		Orientation::Up => 1,
+		Orientation::Down => 2,
+		Orientation::Left => 3,
+		Orientation::Right => 4,
+	}
+}
+
+fn decode_orientation(value: u8) -> Result<Orientation, String> {
+	match value {
+		1 => Ok(Orientation::Up),
+		2 => Ok(Orientation::Down),
+		3 => Ok(Orientation::Left),
+		4 => Ok(Orientation::Right),
+		value => Err(format!("unknown orientation {}", value)),
+	}
+}
+
+fn decode_block_code(value: u16) -> Result<BlockCode, String> {
+	match value {
+		1 => Ok(BlockCode::SimpleSlab),
+		2 => Ok(BlockCode::SimpleWall),
+		value => Err(format!("unknown block code {}", value)),
+	}
+}
+
+/// Serialize the chunks of a region which are in memory.
+pub fn encode_region(map: &Map, (region_x, region_y): (u64, u64)) -> Vec<u8> {
+	let chunks: Vec<&Chunk> = map.chunks().iter()
+		.filter(|chunk| chunk.x / REGION_SIZE == region_x && chunk.y / REGION_SIZE == region_y)
+		.collect();
+
+	let mut data = Vec::with_capacity(HEADER_BYTES + chunks.len() * CHUNK_BYTES);
+	data.extend_from_slice(MAGIC);
+	data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+	data.extend_from_slice(&(chunks.len() as u16).to_le_bytes());
+	for chunk in chunks {
+		let index = (chunk.x % REGION_SIZE / 8 + chunk.y % REGION_SIZE / 8 * 32) as u16;
+		data.extend_from_slice(&index.to_le_bytes());
+		for column in chunk.blocks.iter() {
+			for block in column.iter() {
+				data.extend_from_slice(&(block.get_block_code() as u16).to_le_bytes());
+				data.push(encode_orientation(block.get_orientation()));
+			}
+		}
+	}
+	data
+}
+
+/// Return the format version of a region file.
+pub fn region_version(data: &[u8]) -> Result<u16, String> {
+	if data.len() < 6 || &data[0..4] != MAGIC {
+		return Err(String::from("not a region file"));
+	}
+	Ok(u16::from_le_bytes([data[4], data[5]]))
+}
+
+/// Parse a region file of the current version. The coordinates of the returned chunks are relative to the region.
+pub fn decode_region(data: &[u8]) -> Result<Vec<Chunk>, String> {
+	let version = region_version(data)?;
+	if version != FORMAT_VERSION {
+		return Err(format!("unsupported format version {}", version));
+	}
+	if data.len() < HEADER_BYTES {
+		return Err(String::from("unexpected file size"));
+	}
+	let count = u16::from_le_bytes([data[6], data[7]]) as usize;
+	if data.len() != HEADER_BYTES + count * CHUNK_BYTES {
+		return Err(String::from("unexpected file size"));
+	}
+
+	let mut chunks = Vec::with_capacity(count);
+	for chunk_data in data[HEADER_BYTES..].chunks(CHUNK_BYTES) {
+		let index = u16::from_le_bytes([chunk_data[0], chunk_data[1]]) as u64;
+		if index >= 32 * 32 {
+			return Err(format!("invalid chunk index {}", index));
+		}
+		let mut blocks = [[Block::default(); 8]; 8];
+		for (idx, block_data) in chunk_data[2..].chunks(3).enumerate() {
+			let block_code = decode_block_code(u16::from_le_bytes([block_data[0], block_data[1]]))?;
+			blocks[idx / 8][idx % 8] = Block::new(block_code, decode_orientation(block_data[2])?);
+		}
+		chunks.push(Chunk::new(index % 32 * 8, index / 32 * 8, blocks));
+	}
+	Ok(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_round_trip() {
+		let path = std::env::temp_dir().join(format!("dungeon_world_{}", std::process::id()));
+		let _ = fs::remove_dir_all(&path);
+
+		let center: u64 = 9_223_372_036_854_775_808;
+		let positions = [(center, center), (center + 300, center + 7), (center - 1000, center - 1), (5, 5)];
+		let mut world = World::open(&path).unwrap();
+		for (idx, position) in positions.iter().enumerate() {
+			world.set_block(*position, Block::new(BlockCode::SimpleWall, decode_orientation(idx as u8 + 1).unwrap()));
+		}
+		assert!(world.save().unwrap() >= 4);
+		assert!(!world.has_unsaved_changes());
+		let original: Vec<Chunk> = world.map().chunks().to_vec();
+
+		// regions are only read when needed
+		let mut reloaded = World::open(&path).unwrap();
+		assert!(reloaded.map().chunks().is_empty());
+		for chunk in original.iter() {
+			assert_eq!(reloaded.get_chunk(chunk.x, chunk.y), *chunk);
+		}
+		assert_eq!(reloaded.map().chunks().len(), original.len());
+		assert!(!reloaded.has_unsaved_changes());
+
+		// a corrupted region is moved aside
+		let region_path = World::region_path(&path, (5 / REGION_SIZE, 5 / REGION_SIZE));
+		fs::write(&region_path, b"DGRG garbage").unwrap();
+		let mut reloaded = World::open(&path).unwrap();
+		assert_eq!(reloaded.block((5, 5)), Block::default());
+		assert!(region_path.with_extension("broken").exists());
+
+		// a region which can't be read is kept, and can't be modified
+		fs::create_dir(&region_path).unwrap();
+		let mut reloaded = World::open(&path).unwrap();
+		assert_eq!(reloaded.block((5, 5)), Block::default());
+		assert!(!reloaded.set_block((5, 5), Block::new(BlockCode::SimpleWall, Orientation::Up)));
+		assert!(!reloaded.has_unsaved_changes());
+		assert!(region_path.is_dir());
+		fs::remove_dir(&region_path).unwrap();
+		assert!(reloaded.set_block((5, 5), Block::new(BlockCode::SimpleWall, Orientation::Up)));
+
+		fs::remove_dir_all(&path).unwrap();
+	}
+
+	#[test]
+	fn test_unload_unseen() {
+		let path = std::env::temp_dir().join(format!("dungeon_unload_{}", std::process::id()));
+		let _ = fs::remove_dir_all(&path);
+		let wall = Block::new(BlockCode::SimpleWall, Orientation::Up);
+
+		let mut world = World::open(&path).unwrap();
+		world.save().unwrap();
+		world.set_block((10, 10), wall);
+		world.set_block((REGION_SIZE * 3, 10), wall);
+		let view = ((REGION_SIZE * 3 - 8, 0), (64, 32));
+
+		// the spawn is not seen by anyone but the delay is not over yet
+		assert_eq!(world.unload_unseen(vec![view], Duration::from_secs(60)).unwrap(), 0);
+		assert_eq!(world.unload_unseen(vec![view], Duration::from_secs(0)).unwrap(), 3);
+		assert!(world.map().chunks().iter().all(|chunk| chunk.x / REGION_SIZE >= 2));
+		assert_eq!(world.map().chunks().len(), 1);
+		assert!(world.has_unsaved_changes());
+
+		// unloaded regions have been saved and are loaded again when needed
+		assert_eq!(world.block((10, 10)), wall);
+		assert_eq!(world.unload_unseen(Vec::new(), Duration::from_secs(0)).unwrap(), 2);
+		assert!(world.map().chunks().is_empty());
+		assert!(!world.has_unsaved_changes());
+		assert_eq!(world.block((REGION_SIZE * 3, 10)), wall);
+
+		// a world which is not saved keeps everything
+		let mut world = World::new();
+		let chunks = world.map().chunks().len();
+		assert_eq!(world.unload_unseen(Vec::new(), Duration::from_secs(0)).unwrap(), 0);
+		assert_eq!(world.map().chunks().len(), chunks);
+
+		fs::remove_dir_all(&path).unwrap();
+	}
+}
@@ -7,6 +7,7 @@ use web_sys::MouseEvent;
 /// This struct contains the state of the mouse (position and click)
 /// For now you must not let this struct be dropped!
 pub struct Mouse {
+    #[allow(dead_code)]
     pressed: bool,
     position: Rc<RefCell<(i32, i32)>>,
 }
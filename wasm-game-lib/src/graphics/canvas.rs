@@ -1,5 +1,3 @@
-use crate::events::keyboard::KeyboardManager;
-use crate::events::mouse::Mouse;
 use crate::graphics::image::Image;
 use crate::graphics::linecap::LineCap;
 use wasm_bindgen::JsCast;
@@ -62,7 +62,7 @@ impl Image {
             callback(image);
         }) as Box<dyn FnMut(Event)>);
 
-        Image::new(&url, closure.as_ref().unchecked_ref());
+        Image::new(url, closure.as_ref().unchecked_ref());
 
         closure.forget();
     }
@@ -94,8 +94,8 @@ impl Image {
             if total_images - loaded_images == 0 {
                 let mut images2: Vec<Image> = Vec::new();
 
-                for i in 0..total_images {
-                    if let Some(img) = images[i].take() {
+                for image in images.iter_mut() {
+                    if let Some(img) = image.take() {
                         images2.push(img);
                     } else {
                         panic!("Can't sort images because a url has changed after load ! (maybe a redirection or it was a relative url)");
@@ -106,7 +106,7 @@ impl Image {
         }) as Box<dyn FnMut(Event)>);
 
         for url in urls {
-            Image::new(&url, closure.as_ref().unchecked_ref());
+            Image::new(url, closure.as_ref().unchecked_ref());
         }
 
         closure.forget();
@@ -107,7 +107,7 @@ impl Drawable for &Sprite {
             self.position.1 - self.origin.1,
             self.size.0,
             self.size.1,
-            &self.texture.as_ref().unwrap(),
+            self.texture.as_ref().unwrap(),
         );
     }
 }
@@ -119,7 +119,7 @@ impl Drawable for Sprite {
             self.position.1 - self.origin.1,
             self.size.0,
             self.size.1,
-            &self.texture.as_ref().unwrap(),
+            self.texture.as_ref().unwrap(),
         );
     }
 }